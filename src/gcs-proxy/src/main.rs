@@ -2,21 +2,23 @@ use anyhow::Result;
 use axum::{
     extract::{Path, State},
     http::StatusCode,
-    response::Response,
+    response::{IntoResponse, Json, Response},
     routing::get,
     Router,
 };
-use google_cloud_storage::{
-    client::{Client, ClientConfig},
-    http::objects::{download::Range, get::GetObjectRequest},
-};
 use std::sync::Arc;
+use tenant_routing_core::{
+    health::ReadinessReport,
+    loader::{load_error_messages, load_service_config},
+    source::{MappingSource, SourceConfig, SourceError},
+};
 use tower_http::trace::TraceLayer;
-use tracing::{error, info, Level};
+use tracing::{error, info, warn, Level};
 
 #[derive(Clone)]
 struct AppState {
-    gcs_client: Arc<Client>,
+    source: Arc<dyn MappingSource>,
+    bucket: String,
 }
 
 #[tokio::main]
@@ -31,20 +33,31 @@ async fn main() -> Result<()> {
 
     info!("Initializing GCS proxy service");
 
-    // Initialize GCS client with default credentials
-    let config = ClientConfig::default().with_auth().await?;
-    let gcs_client = Arc::new(Client::new(config));
+    // Same configuration as the lookup service. Refuse to start rather than
+    // serve from a misconfigured bucket
+    let config = load_service_config().inspect_err(|e| {
+        for message in load_error_messages(e) {
+            error!("Invalid configuration: {}", message);
+        }
+    })?;
+    let port = config.port;
+    let bucket = config.gcs_bucket;
+
+    // Initialize mapping source (GCS unless overridden for local runs)
+    let source = SourceConfig::from_env(&bucket)?.build().await?;
+    info!("Mapping source: {}", source.describe());
 
-    let state = AppState { gcs_client };
+    let state = AppState { source, bucket };
 
     // Build router
     let app = Router::new()
         .route("/gcs/*path", get(proxy_gcs_request))
         .route("/health", get(health_check))
+        .route("/livez", get(health_check))
+        .route("/readyz", get(readiness))
         .layer(TraceLayer::new_for_http())
         .with_state(state);
 
-    let port = std::env::var("PORT").unwrap_or_else(|_| "8080".to_string());
     let addr = format!("0.0.0.0:{}", port);
     let listener = tokio::net::TcpListener::bind(&addr).await?;
 
@@ -55,10 +68,27 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Liveness: the process is up and serving requests.
 async fn health_check() -> &'static str {
     "OK"
 }
 
+/// Readiness: the bucket answers with the configured credentials. `503`
+/// with the failing components otherwise.
+async fn readiness(State(state): State<AppState>) -> Response {
+    let report = ReadinessReport::for_source(state.source.as_ref()).await;
+    for (component, error) in report.failures() {
+        warn!("Readiness check failed for {}: {}", component, error);
+    }
+
+    let status = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report)).into_response()
+}
+
 async fn proxy_gcs_request(
     Path(path): Path<String>,
     State(state): State<AppState>,
@@ -73,54 +103,29 @@ async fn proxy_gcs_request(
     let bucket = parts[0];
     let object = parts[1];
 
-    info!("Proxying request for gs://{}/{}", bucket, object);
+    if bucket != state.bucket {
+        warn!("Rejecting request for unconfigured bucket: {}", bucket);
+        return Err(StatusCode::NOT_FOUND);
+    }
 
-    // Create request
-    let request = GetObjectRequest {
-        bucket: bucket.to_string(),
-        object: object.to_string(),
-        ..Default::default()
-    };
+    info!("Proxying request for gs://{}/{}", bucket, object);
 
-    // Try to fetch the object
-    match state.gcs_client.get_object(&request).await {
-        Ok(_metadata) => {
-            // Download the object content
-            match state
-                .gcs_client
-                .download_object(&request, &Range::default())
-                .await
-            {
-                Ok(bytes) => {
-                    match String::from_utf8(bytes) {
-                        Ok(content) => {
-                            info!("Successfully fetched gs://{}/{}", bucket, object);
-                            Ok(Response::builder()
-                                .status(StatusCode::OK)
-                                .header("Content-Type", "text/plain")
-                                .body(content)
-                                .unwrap())
-                        }
-                        Err(e) => {
-                            error!("Content is not valid UTF-8: {}", e);
-                            // Return raw bytes as base64 or handle binary content
-                            Err(StatusCode::INTERNAL_SERVER_ERROR)
-                        }
-                    }
-                }
-                Err(e) => {
-                    error!("Failed to download object content: {}", e);
-                    Err(StatusCode::INTERNAL_SERVER_ERROR)
-                }
-            }
+    match state.source.read_object(object).await {
+        Ok(content) => {
+            info!("Successfully fetched gs://{}/{}", bucket, object);
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "text/plain")
+                .body(content)
+                .unwrap())
+        }
+        Err(SourceError::NotFound(_)) => {
+            info!("Object not found: gs://{}/{}", bucket, object);
+            Err(StatusCode::NOT_FOUND)
         }
         Err(e) => {
-            error!("Failed to get object metadata: {}", e);
-            if e.to_string().contains("404") || e.to_string().contains("not found") {
-                Err(StatusCode::NOT_FOUND)
-            } else {
-                Err(StatusCode::INTERNAL_SERVER_ERROR)
-            }
+            error!("Failed to fetch gs://{}/{}: {}", bucket, object, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
\ No newline at end of file
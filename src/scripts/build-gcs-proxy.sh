@@ -22,15 +22,15 @@ fi
 
 echo -e "${YELLOW}Building GCS proxy service...${NC}"
 
-# Navigate to the GCS proxy directory
-cd "$PROJECT_ROOT/gcs-proxy"
+# Navigate to the parent directory to have access to both gcs-proxy and tenant-routing-core
+cd "$PROJECT_ROOT"
 
 # Get git SHA for tagging
 GIT_SHA=$(git rev-parse --short HEAD)
 
 # Build using Docker
 echo -e "${YELLOW}Building Docker image...${NC}"
-docker build -t gcs-proxy -f Dockerfile --platform=linux/amd64 .
+docker build -t gcs-proxy -f gcs-proxy/Dockerfile --platform=linux/amd64 .
 
 # Tag for GCR with both latest and git SHA
 GCR_IMAGE_LATEST="gcr.io/${PROJECT_ID}/gcs-proxy:latest"
@@ -20,7 +20,10 @@ echo "shard1" | gsutil cp - "gs://$BUCKET/default/shard"
 echo "shard1" | gsutil cp - "gs://$BUCKET/corp/shard"
 echo "shard1" | gsutil cp - "gs://$BUCKET/beamreach/shard"
 echo "shard2" | gsutil cp - "gs://$BUCKET/sfco/shard"
-echo "shard2" | gsutil cp - "gs://$BUCKET/foo/shard"
+echo '{"schema_version":1,"shard":"shard2","metadata":{"tier":"standard"}}' | gsutil cp - "gs://$BUCKET/foo/shard"
+
+# Vanity domain that routes as tenant sfco
+echo "sfco" | gsutil cp - "gs://$BUCKET/_aliases/portal.sfco.test"
 
 echo ""
 echo "Test mappings created:"
@@ -29,10 +32,12 @@ echo "  corp → shard1"
 echo "  beamreach → shard1"
 echo "  sfco → shard2"
 echo "  foo → shard2"
+echo "  portal.sfco.test → sfco (alias)"
 
 echo ""
 echo "To test, use these hostnames:"
 echo "  beamreach.example.com"
 echo "  sfco.example.com"
 echo "  corp.example.com"
-echo "  foo.example.com"
\ No newline at end of file
+echo "  foo.example.com"
+echo "  portal.sfco.test"
\ No newline at end of file
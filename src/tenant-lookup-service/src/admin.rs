@@ -0,0 +1,378 @@
+//! Admin endpoints, served when `ADMIN_TOKEN` is set: editing tenant
+//! mappings under `/admin/tenants/{tenant}`, and inspecting and purging
+//! this instance's cache under `/admin/cache`.
+//!
+//! Writes go to the mapping store with the version the caller last read
+//! (its `ETag`) as a precondition, so concurrent edits are rejected with 412
+//! instead of silently overwriting each other.
+
+use axum::{
+    extract::{Path, State},
+    http::{
+        header::{AUTHORIZATION, ETAG, IF_MATCH, IF_NONE_MATCH, WWW_AUTHENTICATE},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Json, Response},
+};
+use serde::Serialize;
+use std::sync::atomic::Ordering;
+use tenant_routing_core::{
+    mapping::MappingRecord,
+    source::{Precondition, SourceError},
+    tenant::{build_gcs_object_name, canonical_tenant_id, normalize_shard_name},
+};
+use tracing::{error, info};
+
+use crate::{unix_now, AppState, ErrorResponse};
+
+/// A rejected admin request, sent as an [`ErrorResponse`].
+pub struct AdminError {
+    status: StatusCode,
+    message: String,
+}
+
+impl AdminError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<(&str, SourceError)> for AdminError {
+    fn from((tenant, e): (&str, SourceError)) -> Self {
+        match e {
+            SourceError::NotFound(_) => {
+                Self::new(StatusCode::NOT_FOUND, format!("no mapping for tenant {}", tenant))
+            }
+            SourceError::PreconditionFailed(_) => Self::new(
+                StatusCode::PRECONDITION_FAILED,
+                format!("mapping for tenant {} was changed by another writer", tenant),
+            ),
+            SourceError::Backend(_) => {
+                error!("Admin request for tenant {} failed: {}", tenant, e);
+                Self::new(StatusCode::BAD_GATEWAY, e.to_string())
+            }
+        }
+    }
+}
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, Json(ErrorResponse { error: self.message })).into_response();
+        if self.status == StatusCode::UNAUTHORIZED {
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        response
+    }
+}
+
+#[derive(Serialize)]
+struct AdminMappingResponse {
+    tenant: String,
+    /// Version of the stored mapping, also sent as the `ETag`
+    version: String,
+    mapping: MappingRecord,
+}
+
+#[derive(Serialize)]
+pub struct CacheListResponse {
+    entries: Vec<CachedMapping>,
+}
+
+#[derive(Serialize)]
+pub struct CachedMapping {
+    tenant: String,
+    shard: String,
+    /// Seconds since the mapping was fetched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    age_seconds: Option<u64>,
+    /// Seconds until the entry needs refreshing
+    ttl_seconds: u64,
+    freshness: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct PurgeResponse {
+    /// Tenants dropped from the mapping and negative caches
+    purged: usize,
+}
+
+/// Return `tenant`'s stored mapping and its version.
+pub async fn get_mapping(
+    State(state): State<AppState>,
+    Path(tenant): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, AdminError> {
+    let tenant = authorize(&state, &headers, &tenant)?;
+
+    let object = state
+        .source
+        .read_versioned(&build_gcs_object_name(&tenant))
+        .await
+        .map_err(|e| AdminError::from((tenant.as_str(), e)))?;
+
+    match state.config.parse_mapping(&object.contents) {
+        Ok(mapping) => Ok(mapping_response(StatusCode::OK, tenant, object.version, mapping)),
+        // Still send the version so the record can be fixed with a PUT
+        Err(e) => {
+            let error = AdminError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("stored mapping is invalid: {}", e),
+            );
+            Ok(with_etag(error.into_response(), object.version))
+        }
+    }
+}
+
+/// Create or replace `tenant`'s mapping. Requires `If-Match` with the
+/// current version, or `If-None-Match: *` to create a new mapping.
+pub async fn put_mapping(
+    State(state): State<AppState>,
+    Path(tenant): Path<String>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Response, AdminError> {
+    let tenant = authorize(&state, &headers, &tenant)?;
+    let precondition = write_precondition(&headers)?;
+
+    // Same validation as lookups apply, so a mapping that would be rejected
+    // when routing (e.g. to an unregistered shard) is never stored
+    let mut mapping = state.config.parse_mapping(&body).map_err(|e| {
+        AdminError::new(StatusCode::UNPROCESSABLE_ENTITY, format!("invalid mapping: {}", e))
+    })?;
+    mapping.updated_at = Some(unix_now());
+    let contents = serde_json::to_string(&mapping)
+        .map_err(|e| AdminError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let version = state
+        .source
+        .write_object(&build_gcs_object_name(&tenant), &contents, precondition)
+        .await
+        .map_err(|e| AdminError::from((tenant.as_str(), e)))?;
+    info!("Admin updated mapping for tenant {} -> {}", tenant, mapping.shard);
+    invalidate(&state, &tenant).await;
+
+    let status = match precondition {
+        Precondition::DoesNotExist => StatusCode::CREATED,
+        Precondition::Matches(_) => StatusCode::OK,
+    };
+    Ok(mapping_response(status, tenant, version, mapping))
+}
+
+/// Delete `tenant`'s mapping. Requires `If-Match` with the current version.
+pub async fn delete_mapping(
+    State(state): State<AppState>,
+    Path(tenant): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, AdminError> {
+    let tenant = authorize(&state, &headers, &tenant)?;
+    let precondition = match write_precondition(&headers)? {
+        Precondition::DoesNotExist => {
+            return Err(AdminError::new(
+                StatusCode::PRECONDITION_REQUIRED,
+                "deleting a mapping requires If-Match",
+            ))
+        }
+        precondition => precondition,
+    };
+
+    state
+        .source
+        .delete_object(&build_gcs_object_name(&tenant), precondition)
+        .await
+        .map_err(|e| AdminError::from((tenant.as_str(), e)))?;
+    info!("Admin deleted mapping for tenant {}", tenant);
+    invalidate(&state, &tenant).await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// List the mappings in this instance's cache, by tenant.
+pub async fn list_cache(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<CacheListResponse>, AdminError> {
+    check_token(&state, &headers)?;
+
+    let now = unix_now();
+    let mut entries: Vec<CachedMapping> = state
+        .cache
+        .iter()
+        .map(|(tenant, entry)| CachedMapping {
+            tenant: tenant.to_string(),
+            age_seconds: (entry.cached_at != 0).then(|| now.saturating_sub(entry.cached_at)),
+            ttl_seconds: entry.expiry.saturating_sub(now),
+            freshness: entry.freshness(now).as_str(),
+            shard: entry.shard,
+        })
+        .collect();
+    entries.sort_by(|a, b| a.tenant.cmp(&b.tenant));
+
+    Ok(Json(CacheListResponse { entries }))
+}
+
+/// Drop `tenant` from this instance's caches.
+pub async fn purge_tenant(
+    State(state): State<AppState>,
+    Path(tenant): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<PurgeResponse>, AdminError> {
+    let tenant = authorize(&state, &headers, &tenant)?;
+
+    let cached = state.cache.contains_key(&tenant) || state.negative_cache.contains_key(&tenant);
+    invalidate(&state, &tenant).await;
+    info!("Admin purged tenant {} from the cache", tenant);
+
+    Ok(Json(PurgeResponse {
+        purged: usize::from(cached),
+    }))
+}
+
+/// Drop every cached mapping that can route to `shard`, e.g. after moving
+/// tenants off it. Like [`invalidate`], it bumps the write counter first.
+pub async fn purge_shard(
+    State(state): State<AppState>,
+    Path(shard): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<PurgeResponse>, AdminError> {
+    check_token(&state, &headers)?;
+
+    let shard = normalize_shard_name(&shard);
+    state.writes.fetch_add(1, Ordering::SeqCst);
+    let tenants: Vec<_> = state
+        .cache
+        .iter()
+        .filter(|(_, entry)| entry.routes_to(&shard))
+        .map(|(tenant, _)| tenant)
+        .collect();
+    for tenant in &tenants {
+        state.cache.invalidate(tenant.as_str()).await;
+    }
+    info!("Admin purged {} tenants on shard {} from the cache", tenants.len(), shard);
+
+    Ok(Json(PurgeResponse {
+        purged: tenants.len(),
+    }))
+}
+
+/// Drop everything from this instance's mapping, alias and negative caches.
+/// Like [`invalidate`], it bumps the write counter first.
+pub async fn flush_cache(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<PurgeResponse>, AdminError> {
+    check_token(&state, &headers)?;
+
+    let purged = state.cache.iter().count() + state.negative_cache.iter().count();
+    state.writes.fetch_add(1, Ordering::SeqCst);
+    state.cache.invalidate_all();
+    state.alias_cache.invalidate_all();
+    state.negative_cache.invalidate_all();
+    info!("Admin flushed the cache");
+
+    Ok(Json(PurgeResponse { purged }))
+}
+
+/// Check the bearer token and canonicalize the tenant in the path.
+fn authorize(state: &AppState, headers: &HeaderMap, tenant: &str) -> Result<String, AdminError> {
+    check_token(state, headers)?;
+
+    canonical_tenant_id(tenant).ok_or_else(|| {
+        AdminError::new(StatusCode::BAD_REQUEST, format!("invalid tenant ID '{}'", tenant))
+    })
+}
+
+fn check_token(state: &AppState, headers: &HeaderMap) -> Result<(), AdminError> {
+    let Some(token) = &state.admin_token else {
+        return Err(AdminError::new(
+            StatusCode::FORBIDDEN,
+            "admin API is disabled; set ADMIN_TOKEN to enable it",
+        ));
+    };
+
+    let presented = headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if !presented.is_some_and(|presented| constant_time_eq(presented.as_bytes(), token.as_bytes())) {
+        return Err(AdminError::new(StatusCode::UNAUTHORIZED, "invalid admin token"));
+    }
+    Ok(())
+}
+
+/// Compare without exiting early, so response times don't reveal how much
+/// of the token matched.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The precondition a write is made under, from `If-Match: "<version>"`
+/// or `If-None-Match: *`.
+fn write_precondition(headers: &HeaderMap) -> Result<Precondition, AdminError> {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|v: &HeaderValue| v.to_str().ok())
+            .map(str::trim)
+    };
+
+    if let Some(etag) = header(IF_MATCH) {
+        return parse_etag(etag).map(Precondition::Matches).ok_or_else(|| {
+            AdminError::new(
+                StatusCode::BAD_REQUEST,
+                format!("If-Match must be a single ETag, got '{}'", etag),
+            )
+        });
+    }
+    match header(IF_NONE_MATCH) {
+        Some("*") => Ok(Precondition::DoesNotExist),
+        Some(other) => Err(AdminError::new(
+            StatusCode::BAD_REQUEST,
+            format!("If-None-Match only supports '*', got '{}'", other),
+        )),
+        None => Err(AdminError::new(
+            StatusCode::PRECONDITION_REQUIRED,
+            "send If-Match with the mapping's ETag, or If-None-Match: * to create it",
+        )),
+    }
+}
+
+fn parse_etag(etag: &str) -> Option<u64> {
+    etag.strip_prefix('"')?.strip_suffix('"')?.parse().ok()
+}
+
+/// Drop `tenant` from this instance's caches so the next lookup reads the
+/// new mapping. Other instances pick it up when their entries expire.
+///
+/// Bumping the write counter first keeps lookups and refreshes already in
+/// flight from caching the mapping they read before the write.
+async fn invalidate(state: &AppState, tenant: &str) {
+    state.writes.fetch_add(1, Ordering::SeqCst);
+    state.cache.invalidate(tenant).await;
+    state.negative_cache.invalidate(tenant).await;
+}
+
+fn mapping_response(
+    status: StatusCode,
+    tenant: String,
+    version: u64,
+    mapping: MappingRecord,
+) -> Response {
+    let body = AdminMappingResponse {
+        tenant,
+        version: version.to_string(),
+        mapping,
+    };
+    with_etag((status, Json(body)).into_response(), version)
+}
+
+fn with_etag(mut response: Response, version: u64) -> Response {
+    if let Ok(etag) = HeaderValue::from_str(&format!("\"{}\"", version)) {
+        response.headers_mut().insert(ETAG, etag);
+    }
+    response
+}
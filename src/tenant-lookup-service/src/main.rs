@@ -1,35 +1,90 @@
+mod admin;
+mod metrics;
+
 use anyhow::Result;
 use axum::{
     extract::{Query, State},
-    http::StatusCode,
-    response::Json,
-    routing::get,
+    http::{
+        header::{CONTENT_TYPE, RETRY_AFTER},
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Json, Response},
+    routing::{delete, get, post},
     Router,
 };
-use google_cloud_storage::{
-    client::{Client, ClientConfig},
-    http::objects::{download::Range, get::GetObjectRequest},
-};
-use moka::future::Cache;
+use futures_util::{stream, StreamExt};
+use moka::{future::Cache, Expiry};
 use serde::{Deserialize, Serialize};
-use std::{env, sync::Arc, time::Duration};
+use std::{
+    collections::HashSet,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 use tenant_routing_core::{
-    config::TenantRoutingConfig,
-    tenant::{build_gcs_object_name, extract_tenant_from_host, normalize_shard_name},
+    alias::{alias_host, parse_alias_record},
+    cache::{parse_generation_record, CacheEntry, Freshness},
+    config::{BackendErrorPolicy, PrewarmMode, TenantRoutingConfig},
+    extractor::TenantRequest,
+    health::{ComponentStatus, ReadinessReport},
+    loader::{load_error_messages, load_service_config},
+    mapping::{LookupOutcome, MappingError, MappingRecord},
+    migration::{is_write_method, RequestContext, RouteDecision},
+    source::{MappingSource, SourceConfig, SourceError},
+    tenant::canonical_tenant_id,
 };
+use tokio::task::JoinHandle;
 use tower_http::trace::TraceLayer;
-use tracing::{error, info, Level};
+
+use tracing::{debug, error, info, warn, Level};
+
+use crate::metrics::Metrics;
 
 #[derive(Clone)]
 struct AppState {
-    gcs_client: Arc<Client>,
+    source: Arc<dyn MappingSource>,
     config: TenantRoutingConfig,
-    cache: Cache<String, String>,
+    cache: Cache<String, CacheEntry>,
+    /// Alias lookups by host; `None` when the host has no alias
+    alias_cache: Cache<String, Option<String>>,
+    /// Tenants known to have no mapping
+    negative_cache: Cache<String, ()>,
+    /// Tenants whose mapping is being refreshed in the background
+    refreshing: Arc<Mutex<HashSet<String>>>,
+    /// Bumped each time an admin write or purge invalidates cached entries,
+    /// so fetches that started before do not cache what they read. One
+    /// counter for every tenant keeps it from growing with them
+    writes: Arc<AtomicU64>,
+    /// Cache generation the caches were filled under
+    generation: Arc<AtomicU64>,
+    metrics: Metrics,
+    /// Bearer token for the admin endpoints; they are disabled without one
+    admin_token: Option<Arc<str>>,
+    /// Progress of the startup cache pre-warm; `None` when it is disabled
+    prewarm: Arc<Mutex<Option<ComponentStatus>>>,
 }
 
 #[derive(Deserialize)]
 struct LookupParams {
     host: String,
+    /// Path of the routed request, for the path prefix tenant extractor.
+    #[serde(default)]
+    path: String,
+    /// Session or client identifier used to pick a shard when the tenant's
+    /// traffic is split, so the same user keeps landing on the same shard.
+    #[serde(default)]
+    key: String,
+    /// HTTP method of the routed request, used to hold back writes while a
+    /// tenant is frozen for migration.
+    #[serde(default = "default_method")]
+    method: String,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
 }
 
 #[derive(Serialize)]
@@ -37,6 +92,85 @@ struct LookupResponse {
     shard: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     tenant: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    migration_phase: Option<&'static str>,
+    /// How the tenant's mapping lookup ended, see `LookupOutcome`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    outcome: Option<&'static str>,
+    /// Set when `shard` comes from a cached mapping served because the
+    /// mapping store is failing
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stale: bool,
+}
+
+#[derive(Serialize)]
+struct UnavailableResponse {
+    tenant: String,
+    migration_phase: &'static str,
+    retry_after: u64,
+}
+
+/// Body of the 503 sent when the mapping store fails and the backend error
+/// policy is `fail_closed`.
+#[derive(Serialize)]
+struct BackendErrorResponse {
+    tenant: String,
+    outcome: &'static str,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// Most hosts and tenants a single batch lookup may resolve.
+const MAX_BATCH_ITEMS: usize = 1000;
+
+/// Hosts and tenants a batch lookup resolves at once, bounding its concurrent
+/// mapping store fetches.
+const BATCH_CONCURRENCY: usize = 16;
+
+/// First wait before listing the tenants to pre-warm again, doubling after
+/// each failure up to `MAX_PREWARM_RETRY_DELAY`.
+const PREWARM_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_PREWARM_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+struct BatchLookupRequest {
+    /// Hosts, resolved to tenants as `/lookup` does (aliases, then labels).
+    #[serde(default)]
+    hosts: Vec<String>,
+    /// Tenant IDs, looked up directly.
+    #[serde(default)]
+    tenants: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct BatchLookupResponse {
+    results: Vec<BatchLookupResult>,
+}
+
+#[derive(Default, Serialize)]
+struct BatchLookupResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+    tenant: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shard: Option<String>,
+    /// Where `shard` came from: `cache`, `store` or `default`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    outcome: Option<&'static str>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stale: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+enum BatchItem {
+    Host(String),
+    Tenant(String),
 }
 
 #[tokio::main]
@@ -48,46 +182,113 @@ async fn main() -> Result<()> {
         )
         .init();
 
-    // Get configuration from environment
-    let bucket_name = env::var("GCS_BUCKET").unwrap_or_else(|_| "tenant-routing-data".to_string());
-    let default_shard = env::var("DEFAULT_SHARD").unwrap_or_else(|_| "shard1".to_string());
-    let cache_ttl_seconds = env::var("CACHE_TTL")
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(300);
-
-    let port = env::var("PORT")
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(8080);
-
-    let config = TenantRoutingConfig::new(bucket_name, cache_ttl_seconds, default_shard);
+    // Defaults, then CONFIG_FILE, then environment overrides. Refuse to
+    // start rather than route with a half-valid configuration
+    let config = load_service_config().inspect_err(|e| {
+        for message in load_error_messages(e) {
+            error!("Invalid configuration: {}", message);
+        }
+    })?;
+    let port = config.port;
 
     info!("Initializing tenant lookup service");
     info!("GCS bucket: {}", config.gcs_bucket);
     info!("Default shard: {}", config.default_shard);
+    if config.uses_hash_fallback() {
+        info!("Fallback shards: {}", config.fallback_shards.join(","));
+    }
+    if !config.shards.is_empty() {
+        let names: Vec<&str> = config.shards.shards().iter().map(|s| s.name.as_str()).collect();
+        info!("Shard registry: {}", names.join(","));
+    }
+    if !config.base_domains.is_empty() {
+        let domains: Vec<&str> = config.base_domains.iter().map(|d| d.domain.as_str()).collect();
+        info!("Base domains: {}", domains.join(","));
+    }
+    info!("Tenant extractors: {:?}", config.tenant_extractors);
     info!("Cache TTL: {}s", config.cache_ttl_seconds);
     info!("Port: {}", port);
 
-    // Initialize GCS client
-    let gcs_config = ClientConfig::default().with_auth().await?;
-    let gcs_client = Arc::new(Client::new(gcs_config));
+    // Initialize mapping source (GCS unless overridden for local runs)
+    let source = SourceConfig::from_env(&config.gcs_bucket)?.build().await?;
+    info!("Mapping source: {}", source.describe());
 
     // Initialize cache
+    // Entries track their own (jittered) soft expiry; moka only drops them
+    // once they are too old to serve even when the source is failing
+    let metrics = Metrics::new();
     let cache = Cache::builder()
+        .expire_after(MappingExpiry)
+        .max_capacity(10_000)
+        .eviction_listener(metrics.eviction_listener("mapping"))
+        .build();
+    let alias_cache = Cache::builder()
         .time_to_live(Duration::from_secs(config.cache_ttl_seconds))
         .max_capacity(10_000)
+        .eviction_listener(metrics.eviction_listener("alias"))
+        .build();
+    let negative_cache = Cache::builder()
+        .time_to_live(Duration::from_secs(config.negative_cache_ttl_seconds))
+        .max_capacity(10_000)
+        .eviction_listener(metrics.eviction_listener("negative"))
         .build();
 
     let state = AppState {
-        gcs_client,
+        source,
         config,
         cache,
+        alias_cache,
+        negative_cache,
+        refreshing: Arc::default(),
+        writes: Arc::default(),
+        generation: Arc::default(),
+        metrics,
+        admin_token: std::env::var("ADMIN_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty())
+            .map(Arc::from),
+        prewarm: Arc::default(),
     };
+    if state.admin_token.is_some() {
+        info!("Admin API enabled");
+    }
+
+    // Follow the cache generation so bumping it flushes every cache
+    if state.config.generation_check_seconds > 0 {
+        check_generation(&state).await;
+
+        let state = state.clone();
+        tokio::spawn(async move {
+            let period = Duration::from_secs(state.config.generation_check_seconds);
+            let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            loop {
+                interval.tick().await;
+                check_generation(&state).await;
+            }
+        });
+    }
+
+    // Fill the cache in the background; /readyz waits for it
+    if state.config.prewarm != PrewarmMode::Off {
+        start_prewarm(&state);
+    }
 
     let app = Router::new()
         .route("/lookup", get(lookup_tenant))
+        .route("/lookup/batch", post(lookup_batch))
         .route("/health", get(health_check))
+        .route("/livez", get(health_check))
+        .route("/readyz", get(readiness))
+        .route("/metrics", get(export_metrics))
+        .route(
+            "/admin/tenants/:tenant",
+            get(admin::get_mapping)
+                .put(admin::put_mapping)
+                .delete(admin::delete_mapping),
+        )
+        .route("/admin/cache", get(admin::list_cache).delete(admin::flush_cache))
+        .route("/admin/cache/tenants/:tenant", delete(admin::purge_tenant))
+        .route("/admin/cache/shards/:shard", delete(admin::purge_shard))
         .layer(TraceLayer::new_for_http())
         .with_state(state);
 
@@ -100,89 +301,1688 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Liveness: the process is up and serving requests.
 async fn health_check() -> &'static str {
     "OK"
 }
 
+/// Readiness: the mapping store answers, so lookups of uncached tenants
+/// can succeed. `503` with the failing components otherwise.
+async fn readiness(State(state): State<AppState>) -> Response {
+    let mut report = ReadinessReport::for_source(state.source.as_ref()).await;
+    if let Some(prewarm) = state.prewarm.lock().unwrap().clone() {
+        report.add("cache_prewarm", prewarm);
+    }
+    for (component, error) in report.failures() {
+        warn!("Readiness check failed for {}: {}", component, error);
+    }
+
+    let status = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report)).into_response()
+}
+
+async fn export_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    state.metrics.set_cache_entries("mapping", state.cache.entry_count());
+    state.metrics.set_cache_entries("alias", state.alias_cache.entry_count());
+    state.metrics.set_cache_entries("negative", state.negative_cache.entry_count());
+
+    ([(CONTENT_TYPE, metrics::CONTENT_TYPE)], state.metrics.render())
+}
+
+/// Identifies the tenant from the `host` and `path` parameters and from
+/// request headers, which the caller forwards from the routed request (e.g.
+/// `x-tenant-id` or `authorization`) for the header and JWT extractors.
 async fn lookup_tenant(
     Query(params): Query<LookupParams>,
     State(state): State<AppState>,
-) -> Result<Json<LookupResponse>, StatusCode> {
-    let host = params.host;
-    let tenant = extract_tenant_from_host(&host);
+    headers: HeaderMap,
+) -> Response {
+    let host = &params.host;
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+    };
+    let request = |alias| TenantRequest {
+        host,
+        path: &params.path,
+        headers: &header,
+        alias,
+    };
+
+    let alias = if state.config.needs_alias(&request(None)) {
+        resolve_alias(&state, host).await
+    } else {
+        None
+    };
+    let tenant = state.config.extract_request_tenant(&request(alias));
 
     match tenant {
         Some(tenant_name) => {
-            // Check cache first
-            if let Some(cached_shard) = state.cache.get(&tenant_name).await {
-                info!("Cache hit for tenant: {} -> {}", tenant_name, cached_shard);
+            let resolution = tenant_mapping(&state, &tenant_name).await;
+            match &resolution.entry {
+                Some(entry) => {
+                    route_tenant(&state, entry, tenant_name, resolution.outcome, &params)
+                }
+                None => unmapped_tenant(&state, tenant_name, resolution.outcome),
+            }
+        }
+        None => {
+            info!(
+                "No tenant extracted from host: {}, using default shard",
+                host
+            );
+            state
+                .metrics
+                .lookup("no_tenant", Some(&state.config.default_shard));
+            Json(LookupResponse {
+                shard: state.config.default_shard.clone(),
+                tenant: None,
+                migration_phase: None,
+                outcome: None,
+                stale: false,
+            })
+            .into_response()
+        }
+    }
+}
+
+/// Resolves many hosts and tenant IDs in one request, through the same caches
+/// as `/lookup`. Results are in request order, hosts first; items the caches
+/// cannot answer are fetched concurrently, `BATCH_CONCURRENCY` at a time.
+async fn lookup_batch(
+    State(state): State<AppState>,
+    Json(request): Json<BatchLookupRequest>,
+) -> Response {
+    let count = request.hosts.len() + request.tenants.len();
+    if count > MAX_BATCH_ITEMS {
+        return (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(ErrorResponse {
+                error: format!(
+                    "a batch may hold at most {} hosts and tenants, got {}",
+                    MAX_BATCH_ITEMS, count
+                ),
+            }),
+        )
+            .into_response();
+    }
 
-                return Ok(Json(LookupResponse {
-                    shard: cached_shard,
-                    tenant: Some(tenant_name),
-                }));
+    let items = (request.hosts.into_iter().map(BatchItem::Host))
+        .chain(request.tenants.into_iter().map(BatchItem::Tenant));
+    let results = stream::iter(items)
+        .map(|item| lookup_batch_item(&state, item))
+        .buffered(BATCH_CONCURRENCY)
+        .collect()
+        .await;
+
+    Json(BatchLookupResponse { results }).into_response()
+}
+
+async fn lookup_batch_item(state: &AppState, item: BatchItem) -> BatchLookupResult {
+    let (host, tenant) = match item {
+        BatchItem::Host(host) => {
+            let request = |alias| TenantRequest {
+                host: &host,
+                path: "",
+                headers: &|_| None,
+                alias,
+            };
+            let alias = if state.config.needs_alias(&request(None)) {
+                resolve_alias(state, &host).await
+            } else {
+                None
+            };
+            let tenant = state.config.extract_request_tenant(&request(alias));
+            (Some(host), tenant)
+        }
+        BatchItem::Tenant(tenant) => match canonical_tenant_id(&tenant) {
+            Some(id) => (None, Some(id)),
+            None => {
+                return BatchLookupResult {
+                    error: Some(format!("invalid tenant ID '{}'", tenant)),
+                    tenant: Some(tenant),
+                    ..Default::default()
+                };
             }
+        },
+    };
 
-            // Fetch from GCS
-            match fetch_tenant_mapping(&state.gcs_client, &state.config.gcs_bucket, &tenant_name)
-                .await
-            {
-                Ok(shard) => {
-                    let normalized_shard = normalize_shard_name(&shard);
+    let Some(tenant) = tenant else {
+        let shard = state.config.default_shard.clone();
+        state.metrics.lookup("no_tenant", Some(&shard));
+        return BatchLookupResult {
+            host,
+            shard: Some(shard),
+            source: Some("default"),
+            ..Default::default()
+        };
+    };
 
-                    info!(
-                        "GCS lookup for tenant: {} -> {}",
-                        tenant_name, normalized_shard
-                    );
+    let resolution = tenant_mapping(state, &tenant).await;
+    let (shard, source) = match &resolution.entry {
+        Some(entry) => {
+            // Resolved as a read, so a tenant frozen for migration still has a shard
+            let ctx = RequestContext {
+                tenant: &tenant,
+                key: "",
+                is_write: false,
+                now: unix_now(),
+            };
+            let shard = match entry.route(&ctx) {
+                RouteDecision::Forward { shard, .. } => {
+                    Some(state.config.shards.resolve(shard).unwrap_or(shard).to_string())
+                }
+                RouteDecision::Unavailable { .. } => None,
+            };
+            (shard, if resolution.cached { "cache" } else { "store" })
+        }
+        None if resolution.outcome == LookupOutcome::BackendError
+            && state.config.backend_error_policy == BackendErrorPolicy::FailClosed =>
+        {
+            (None, "default")
+        }
+        None => (Some(state.config.fallback_shard(&tenant).to_string()), "default"),
+    };
+    state.metrics.lookup(resolution.outcome.as_str(), shard.as_deref());
+
+    BatchLookupResult {
+        host,
+        tenant: Some(tenant),
+        source: shard.is_some().then_some(source),
+        shard,
+        outcome: Some(resolution.outcome.as_str()),
+        stale: resolution.outcome == LookupOutcome::BackendError && resolution.entry.is_some(),
+        error: resolution.error,
+    }
+}
+
+/// Evicts mapping entries at their hard expiry.
+struct MappingExpiry;
+
+impl MappingExpiry {
+    fn remaining(entry: &CacheEntry) -> Option<Duration> {
+        Some(Duration::from_secs(
+            entry.hard_expiry().saturating_sub(unix_now()),
+        ))
+    }
+}
+
+impl Expiry<String, CacheEntry> for MappingExpiry {
+    fn expire_after_create(
+        &self,
+        _tenant: &String,
+        entry: &CacheEntry,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        Self::remaining(entry)
+    }
+
+    fn expire_after_update(
+        &self,
+        _tenant: &String,
+        entry: &CacheEntry,
+        _updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        Self::remaining(entry)
+    }
+}
+
+/// Why a tenant's mapping could not be fetched.
+#[derive(Debug)]
+enum LookupError {
+    NotFound,
+    InvalidRecord(MappingError),
+    Backend(SourceError),
+}
+
+impl LookupError {
+    fn outcome(&self) -> LookupOutcome {
+        match self {
+            LookupError::NotFound => LookupOutcome::NotFound,
+            LookupError::InvalidRecord(_) => LookupOutcome::InvalidRecord,
+            LookupError::Backend(_) => LookupOutcome::BackendError,
+        }
+    }
+}
+
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LookupError::NotFound => write!(f, "no mapping"),
+            LookupError::InvalidRecord(e) => write!(f, "{}", e),
+            LookupError::Backend(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for LookupError {}
+
+/// How a tenant's mapping lookup was answered.
+struct Resolution {
+    outcome: LookupOutcome,
+    /// Mapping to route with. Comes with [`LookupOutcome::BackendError`] when
+    /// a stale entry is served because the source is failing.
+    entry: Option<CacheEntry>,
+    /// Whether the answer came from the mapping or negative cache rather than
+    /// the mapping store
+    cached: bool,
+    /// Why the mapping could not be used, for invalid records and backend errors
+    error: Option<String>,
+}
+
+impl Resolution {
+    fn hit(entry: Option<CacheEntry>) -> Self {
+        Self {
+            outcome: LookupOutcome::Found,
+            entry,
+            cached: true,
+            error: None,
+        }
+    }
+
+    fn fetched(entry: CacheEntry) -> Self {
+        Self {
+            outcome: LookupOutcome::Found,
+            entry: Some(entry),
+            cached: false,
+            error: None,
+        }
+    }
+
+    fn failed(error: &LookupError, stale: Option<CacheEntry>) -> Self {
+        Self {
+            outcome: error.outcome(),
+            cached: stale.is_some(),
+            entry: stale,
+            error: match error {
+                LookupError::NotFound => None,
+                _ => Some(error.to_string()),
+            },
+        }
+    }
+}
+
+/// Mapping for `tenant`, from the cache or the source as the cached entry's
+/// freshness requires, and how the lookup ended.
+async fn tenant_mapping(state: &AppState, tenant: &str) -> Resolution {
+    let now = unix_now();
+    let cached = state.cache.get(tenant).await;
+    let policy = state.config.backend_error_policy;
 
-                    state
-                        .cache
-                        .insert(tenant_name.clone(), normalized_shard.clone())
-                        .await;
+    match cached.as_ref().map(|entry| entry.freshness(now)) {
+        Some(Freshness::Fresh) => {
+            debug!("Cache hit for tenant: {}", tenant);
+            state.metrics.cache_hit("mapping");
+            return Resolution::hit(cached);
+        }
+        Some(Freshness::RefreshAhead) => {
+            // Only tenants still being requested get here, so idle ones
+            // are left to expire
+            debug!("Cache hit for tenant: {}, refreshing ahead of expiry", tenant);
+            state.metrics.cache_hit("mapping");
+            spawn_refresh(state, tenant);
+            return Resolution::hit(cached);
+        }
+        Some(Freshness::Revalidate) => {
+            debug!("Serving stale mapping for tenant {} while refreshing it", tenant);
+            state.metrics.cache_hit("mapping");
+            spawn_refresh(state, tenant);
+            return Resolution::hit(cached);
+        }
+        Some(Freshness::Stale) if policy.serves_stale() => {
+            // Refresh before routing, keeping the last known shard if the
+            // source is failing. Requests arriving while the refresh is in
+            // flight get the stale entry rather than fetching it again
+            let Some(refresh) = spawn_refresh(state, tenant) else {
+                debug!("Serving stale mapping for tenant {} while it is refreshed", tenant);
+                state.metrics.cache_hit("mapping");
+                return Resolution::hit(cached);
+            };
+            state.metrics.cache_miss("mapping");
 
-                    Ok(Json(LookupResponse {
-                        shard: normalized_shard,
-                        tenant: Some(tenant_name),
-                    }))
+            let error = match refresh.await {
+                Ok(Ok(entry)) => return Resolution::fetched(entry),
+                Ok(Err(e @ (LookupError::NotFound | LookupError::InvalidRecord(_)))) => {
+                    return Resolution::failed(&e, None);
                 }
-                Err(e) => {
-                    error!("Failed to fetch tenant mapping for {}: {}", tenant_name, e);
+                Ok(Err(e)) => e,
+                Err(e) => LookupError::Backend(SourceError::Backend(e.to_string())),
+            };
+
+            if let Some(stale) = &cached {
+                warn!(
+                    "Failed to refresh mapping for {}, serving stale shard {}",
+                    tenant, stale.shard
+                );
+            }
+            return Resolution::failed(&error, cached);
+        }
+        // Expired entries are normally already evicted, but must not be
+        // picked up below
+        Some(Freshness::Stale | Freshness::Expired) => state.cache.invalidate(tenant).await,
+        None => {}
+    }
+
+    state.metrics.cache_miss("mapping");
+    if state.negative_cache.contains_key(tenant) {
+        debug!("Negative cache hit for tenant: {}", tenant);
+        state.metrics.cache_hit("negative");
+        return Resolution {
+            outcome: LookupOutcome::NotFound,
+            entry: None,
+            cached: true,
+            error: None,
+        };
+    }
+    state.metrics.cache_miss("negative");
+
+    // Concurrent misses for the tenant share one fetch. Its error is
+    // returned to every waiting request and nothing is cached, so the next
+    // request tries again
+    let epoch = fetch_epoch(state);
+    let result = state
+        .cache
+        .try_get_with(tenant.to_string(), load_mapping(state, tenant))
+        .await;
+    discard_if_invalidated(state, tenant, epoch).await;
 
-                    Ok(Json(LookupResponse {
-                        shard: state.config.default_shard.clone(),
-                        tenant: Some(tenant_name),
-                    }))
+    match result {
+        Ok(entry) => Resolution::fetched(entry),
+        Err(e) => {
+            match *e {
+                LookupError::NotFound => {}
+                LookupError::InvalidRecord(_) => {
+                    warn!("Invalid mapping for tenant {}: {}", tenant, e)
+                }
+                LookupError::Backend(_) => {
+                    error!("Failed to fetch tenant mapping for {}: {}", tenant, e)
                 }
             }
+            Resolution::failed(&e, None)
         }
-        None => {
+    }
+}
+
+/// Fetch `tenant`'s mapping as a cache entry. A missing mapping is
+/// remembered in the negative cache.
+async fn load_mapping(state: &AppState, tenant: &str) -> Result<CacheEntry, LookupError> {
+    match fetch_tenant_mapping(state, tenant).await {
+        Ok(record) => {
+            info!("Source lookup for tenant: {} -> {}", tenant, record.shard);
+            Ok(state.config.cache_entry(tenant, record, unix_now()))
+        }
+        Err(e) => {
+            if let LookupError::NotFound = e {
+                info!("No mapping for tenant: {}", tenant);
+
+                if state.config.negative_cache_ttl_seconds > 0 {
+                    state.negative_cache.insert(tenant.to_string(), ()).await;
+                }
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Fetch `tenant`'s mapping and cache it. A missing mapping replaces any
+/// cached one with a negative cache entry; errors leave the cache alone.
+async fn refresh_mapping(state: &AppState, tenant: &str) -> Result<CacheEntry, LookupError> {
+    let epoch = fetch_epoch(state);
+    let result = load_mapping(state, tenant).await;
+    match &result {
+        Ok(entry) => state.cache.insert(tenant.to_string(), entry.clone()).await,
+        Err(LookupError::NotFound) => state.cache.invalidate(tenant).await,
+        Err(_) => {}
+    }
+    discard_if_invalidated(state, tenant, epoch).await;
+    result
+}
+
+/// When a fetch started: the cache generation, and how many admin writes
+/// and purges had invalidated cached entries.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct FetchEpoch {
+    generation: u64,
+    writes: u64,
+}
+
+fn fetch_epoch(state: &AppState) -> FetchEpoch {
+    FetchEpoch {
+        generation: state.generation.load(Ordering::SeqCst),
+        writes: state.writes.load(Ordering::SeqCst),
+    }
+}
+
+/// Drop what a fetch that started at `epoch` cached for `tenant` if an
+/// admin write or purge or a cache generation change may have invalidated
+/// it since.
+/// Both bump their counter before invalidating, so either this sees the
+/// bump or the invalidation comes after the fetch's insert. A write to
+/// another tenant drops the fetch too, which only costs a refetch.
+async fn discard_if_invalidated(state: &AppState, tenant: &str, epoch: FetchEpoch) {
+    if fetch_epoch(state) != epoch {
+        debug!("Mapping for tenant {} changed while it was fetched, dropping it", tenant);
+        state.cache.invalidate(tenant).await;
+        state.negative_cache.invalidate(tenant).await;
+    }
+}
+
+/// Pre-warm the cache in a task, reporting the cache as not ready until it
+/// finishes. If the tenants cannot be listed, the error is reported and the
+/// listing retried with backoff.
+fn start_prewarm(state: &AppState) -> JoinHandle<()> {
+    *state.prewarm.lock().unwrap() = Some(ComponentStatus::failing("pre-warming the cache"));
+
+    let state = state.clone();
+    tokio::spawn(async move {
+        let started = Instant::now();
+        let mut delay = PREWARM_RETRY_DELAY;
+        loop {
+            match prewarm_cache(&state).await {
+                Ok((cached, total)) => {
+                    info!(
+                        "Pre-warmed {} of {} tenants in {:?}",
+                        cached,
+                        total,
+                        started.elapsed()
+                    );
+                    break;
+                }
+                // Lookups still work meanwhile, with a cold cache
+                Err(e) => {
+                    warn!("Failed to list tenants to pre-warm, retrying in {:?}: {}", delay, e);
+                    *state.prewarm.lock().unwrap() =
+                        Some(ComponentStatus::failing(format!("failed to list tenants: {}", e)));
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_PREWARM_RETRY_DELAY);
+                }
+            }
+        }
+        *state.prewarm.lock().unwrap() = Some(ComponentStatus::ready().with_latency(started.elapsed()));
+    })
+}
+
+/// Cache the mappings of the tenants `config.prewarm` names, fetching
+/// `prewarm_concurrency` at a time and no more than the cache holds. Returns
+/// how many were cached, and of how many tenants.
+async fn prewarm_cache(state: &AppState) -> Result<(usize, usize), SourceError> {
+    let mut tenants: Vec<String> = match state.config.prewarm {
+        PrewarmMode::Off => return Ok((0, 0)),
+        PrewarmMode::Bucket => state.source.list_tenants().await?,
+        PrewarmMode::Tenants => state
+            .config
+            .prewarm_tenants
+            .iter()
+            .filter_map(|tenant| canonical_tenant_id(tenant))
+            .collect(),
+    };
+    // Fetching more would only evict what was just cached
+    if let Some(capacity) = state.cache.policy().max_capacity()
+        && let Ok(capacity) = usize::try_from(capacity)
+        && tenants.len() > capacity
+    {
+        warn!(
+            "Pre-warming only {} of {} tenants, the cache's capacity",
+            capacity,
+            tenants.len()
+        );
+        tenants.truncate(capacity);
+    }
+    let total = tenants.len();
+    info!("Pre-warming the cache with {} tenants", total);
+
+    let concurrency = usize::try_from(state.config.prewarm_concurrency).unwrap_or(usize::MAX);
+    let cached = stream::iter(tenants)
+        .map(|tenant| async move { refresh_mapping(state, &tenant).await.is_ok() })
+        .buffer_unordered(concurrency)
+        .filter(|cached| std::future::ready(*cached))
+        .count()
+        .await;
+
+    Ok((cached, total))
+}
+
+/// Refresh `tenant`'s mapping in a task, once at a time per tenant. `None`
+/// when a refresh is already in flight.
+fn spawn_refresh(
+    state: &AppState,
+    tenant: &str,
+) -> Option<JoinHandle<Result<CacheEntry, LookupError>>> {
+    if !state.refreshing.lock().unwrap().insert(tenant.to_string()) {
+        return None;
+    }
+
+    let state = state.clone();
+    let tenant = tenant.to_string();
+    Some(tokio::spawn(async move {
+        let result = refresh_mapping(&state, &tenant).await;
+        if let Err(e @ (LookupError::Backend(_) | LookupError::InvalidRecord(_))) = &result {
+            warn!("Refresh for tenant {} failed: {}", tenant, e);
+        }
+        state.refreshing.lock().unwrap().remove(&tenant);
+        result
+    }))
+}
+
+/// Re-read the cache generation and, if it changed, drop every cached
+/// mapping, alias and negative entry.
+async fn check_generation(state: &AppState) {
+    let generation = match state.source.read_generation().await {
+        Ok(raw) => match parse_generation_record(&raw) {
+            Ok(generation) => generation,
+            Err(e) => {
+                warn!("Ignoring invalid cache generation: {}", e);
+                return;
+            }
+        },
+        Err(SourceError::NotFound(_)) => 0,
+        Err(e) => {
+            warn!("Failed to read cache generation: {}", e);
+            return;
+        }
+    };
+
+    // Swapped before flushing, so fetches already in flight drop what they
+    // read (see `discard_if_invalidated`)
+    let previous = state.generation.swap(generation, Ordering::SeqCst);
+    if previous != generation {
+        info!(
+            "Cache generation changed from {} to {}, invalidating caches",
+            previous, generation
+        );
+        state.cache.invalidate_all();
+        state.alias_cache.invalidate_all();
+        state.negative_cache.invalidate_all();
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Response for a tenant without a usable mapping, following the backend
+/// error policy when the lookup failed.
+fn unmapped_tenant(state: &AppState, tenant_name: String, outcome: LookupOutcome) -> Response {
+    if outcome == LookupOutcome::BackendError
+        && state.config.backend_error_policy == BackendErrorPolicy::FailClosed
+    {
+        warn!("Mapping store unavailable, rejecting request for tenant {}", tenant_name);
+        state.metrics.lookup(outcome.as_str(), None);
+
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(BackendErrorResponse {
+                tenant: tenant_name,
+                outcome: outcome.as_str(),
+            }),
+        )
+            .into_response();
+    }
+
+    let shard = state.config.fallback_shard(&tenant_name).to_string();
+    state.metrics.lookup(outcome.as_str(), Some(&shard));
+
+    Json(LookupResponse {
+        shard,
+        tenant: Some(tenant_name),
+        migration_phase: None,
+        outcome: Some(outcome.as_str()),
+        stale: false,
+    })
+    .into_response()
+}
+
+fn route_tenant(
+    state: &AppState,
+    entry: &CacheEntry,
+    tenant_name: String,
+    outcome: LookupOutcome,
+    params: &LookupParams,
+) -> Response {
+    let ctx = RequestContext {
+        tenant: &tenant_name,
+        key: &params.key,
+        is_write: is_write_method(&params.method),
+        now: unix_now(),
+    };
+
+    match entry.route(&ctx) {
+        RouteDecision::Forward { shard, phase } => {
+            // Records are validated before caching, so only failover applies here
+            let shard = state.config.shards.resolve(shard).unwrap_or(shard);
+            state.metrics.lookup(outcome.as_str(), Some(shard));
+
+            Json(LookupResponse {
+                shard: shard.to_string(),
+                migration_phase: phase.map(|p| p.as_str()),
+                tenant: Some(tenant_name),
+                // A mapping is only returned without being found when served stale
+                outcome: Some(outcome.as_str()),
+                stale: outcome == LookupOutcome::BackendError,
+            })
+            .into_response()
+        }
+        RouteDecision::Unavailable {
+            phase,
+            retry_after_seconds,
+        } => {
             info!(
-                "No tenant extracted from host: {}, using default shard",
-                host
+                "Tenant {} is {}, rejecting {} request",
+                tenant_name,
+                phase.as_str(),
+                params.method
             );
-            Ok(Json(LookupResponse {
-                shard: state.config.default_shard.clone(),
-                tenant: None,
-            }))
+            state.metrics.lookup(outcome.as_str(), None);
+
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(RETRY_AFTER, retry_after_seconds.to_string())],
+                Json(UnavailableResponse {
+                    tenant: tenant_name,
+                    migration_phase: phase.as_str(),
+                    retry_after: retry_after_seconds,
+                }),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Tenant a vanity host is aliased to, if any.
+async fn resolve_alias(state: &AppState, authority: &str) -> Option<String> {
+    let host = alias_host(authority)?;
+
+    if let Some(cached) = state.alias_cache.get(&host).await {
+        state.metrics.cache_hit("alias");
+        return cached;
+    }
+    state.metrics.cache_miss("alias");
+
+    let epoch = fetch_epoch(state);
+    let fetch = state.metrics.start_fetch("alias");
+    let read = state.source.read_alias(&host).await;
+    drop(fetch);
+
+    let tenant = match read {
+        Ok(raw) => match parse_alias_record(&raw) {
+            Ok(tenant) => {
+                info!("Alias lookup for host: {} -> {}", host, tenant);
+                Some(tenant)
+            }
+            Err(e) => {
+                warn!("Ignoring invalid alias for host {}: {}", host, e);
+                None
+            }
+        },
+        Err(SourceError::NotFound(_)) => None,
+        Err(e) => {
+            // Not cached, so the next request retries the lookup
+            error!("Failed to fetch alias for host {}: {}", host, e);
+            return None;
         }
+    };
+
+    state.alias_cache.insert(host.clone(), tenant.clone()).await;
+    if fetch_epoch(state) != epoch {
+        // Read before the caches were flushed
+        state.alias_cache.invalidate(&host).await;
     }
+    tenant
 }
 
-async fn fetch_tenant_mapping(client: &Client, bucket: &str, tenant: &str) -> Result<String> {
-    let object_name = build_gcs_object_name(tenant);
+async fn fetch_tenant_mapping(state: &AppState, tenant: &str) -> Result<MappingRecord, LookupError> {
+    let fetch = state.metrics.start_fetch("mapping");
+    let read = state.source.read_tenant_mapping(tenant).await;
+    drop(fetch);
+
+    let raw = match read {
+        Ok(raw) => raw,
+        Err(SourceError::NotFound(_)) => return Err(LookupError::NotFound),
+        Err(e) => return Err(LookupError::Backend(e)),
+    };
+
+    let record = state.config.parse_mapping(&raw).inspect_err(|e| {
+        if let MappingError::UnknownShard(shard) = e {
+            state.metrics.unknown_shard_mappings.inc();
+            let rejected = state.metrics.unknown_shard_mappings.get();
+            warn!(
+                "Rejecting mapping for tenant {} to unknown shard {} ({} rejected since start)",
+                tenant, shard, rejected
+            );
+        }
+    });
+
+    record.map_err(LookupError::InvalidRecord)
+}
 
-    let request = GetObjectRequest {
-        bucket: bucket.to_string(),
-        object: object_name.clone(),
-        ..Default::default()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tenant_routing_core::{
+        cache::jittered_ttl,
+        extractor::TenantExtractor,
+        registry::ShardRegistry,
+        source::{InMemorySource, LocalDirSource, Precondition, VersionedObject},
+        tenant::BaseDomain,
+        test_vectors::HOST_VECTORS,
     };
 
-    match client.get_object(&request).await {
-        Ok(_) => {
-            let bytes = client.download_object(&request, &Range::default()).await?;
-            let shard = String::from_utf8(bytes)?.trim().to_string();
-            Ok(shard)
+    fn test_state(base_domains: &str) -> AppState {
+        state_with_source(base_domains, InMemorySource::new())
+    }
+
+    fn state_with_source(base_domains: &str, source: InMemorySource) -> AppState {
+        AppState {
+            source: Arc::new(source),
+            config: TenantRoutingConfig {
+                base_domains: BaseDomain::from_spec(base_domains).unwrap(),
+                ..Default::default()
+            },
+            cache: Cache::new(100),
+            alias_cache: Cache::new(100),
+            negative_cache: Cache::new(100),
+            refreshing: Arc::default(),
+            writes: Arc::default(),
+            generation: Arc::default(),
+            metrics: Metrics::new(),
+            admin_token: None,
+            prewarm: Arc::default(),
+        }
+    }
+
+    fn lookup_params(host: &str) -> LookupParams {
+        LookupParams {
+            host: host.to_string(),
+            path: String::new(),
+            key: String::new(),
+            method: default_method(),
+        }
+    }
+
+    async fn lookup_request(
+        state: &AppState,
+        params: LookupParams,
+        headers: HeaderMap,
+    ) -> (StatusCode, serde_json::Value) {
+        let response = lookup_tenant(Query(params), State(state.clone()), headers).await;
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    async fn lookup_response(state: &AppState, host: &str) -> (StatusCode, serde_json::Value) {
+        lookup_request(state, lookup_params(host), HeaderMap::new()).await
+    }
+
+    async fn lookup_host(state: &AppState, host: &str) -> serde_json::Value {
+        lookup_response(state, host).await.1
+    }
+
+    #[tokio::test]
+    async fn test_lookup_host_vectors() {
+        for vector in HOST_VECTORS {
+            let state = test_state(vector.base_domains);
+            let body = lookup_host(&state, vector.authority).await;
+
+            assert_eq!(
+                body.get("tenant").and_then(|t| t.as_str()),
+                vector.tenant,
+                "authority {:?} with base domains {:?}",
+                vector.authority,
+                vector.base_domains
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lookup_alias() {
+        let source = InMemorySource::new();
+        source.insert_alias("portal.customer.com", "customer");
+        source.insert_tenant("customer", "shard2");
+        let state = state_with_source("example.com", source);
+
+        let body = lookup_host(&state, "Portal.Customer.com:443").await;
+
+        assert_eq!(body["tenant"], "customer");
+        assert_eq!(body["shard"], "shard2");
+        assert_eq!(
+            state.alias_cache.get("portal.customer.com").await,
+            Some(Some("customer".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lookup_forwarded_tenant_header() {
+        let source = InMemorySource::new();
+        source.insert_tenant("acme", "shard3");
+        let mut state = state_with_source("", source);
+        state.config.tenant_extractors = TenantExtractor::from_spec("header, host").unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-tenant-id", "acme".parse().unwrap());
+        let params = LookupParams {
+            path: "/orders".to_string(),
+            ..lookup_params("api.example.com")
+        };
+
+        let (_, body) = lookup_request(&state, params, headers).await;
+
+        assert_eq!(body["tenant"], "acme");
+        assert_eq!(body["shard"], "shard3");
+    }
+
+    #[tokio::test]
+    async fn test_lookup_unknown_tenant_is_negatively_cached() {
+        let state = test_state("example.com");
+
+        let body = lookup_host(&state, "nobody.example.com").await;
+
+        assert_eq!(body["tenant"], "nobody");
+        assert_eq!(body["shard"], state.config.default_shard);
+        assert!(state.negative_cache.contains_key("nobody"));
+        assert!(state.cache.get("nobody").await.is_none());
+    }
+
+    /// Jittered TTL of acme's entries.
+    fn acme_ttl(state: &AppState) -> u64 {
+        jittered_ttl(
+            "acme",
+            state.config.cache_ttl_seconds,
+            state.config.cache_ttl_jitter_percent,
+        )
+    }
+
+    /// An entry for acme on `shard` fetched `age` seconds ago.
+    fn acme_entry(state: &AppState, shard: &str, age: u64) -> CacheEntry {
+        state
+            .config
+            .cache_entry("acme", MappingRecord::new(shard.to_string()), unix_now() - age)
+    }
+
+    /// An entry for acme on `shard` that expired `age` seconds ago.
+    fn expired_entry(state: &AppState, shard: &str, age: u64) -> CacheEntry {
+        acme_entry(state, shard, acme_ttl(state) + age)
+    }
+
+    #[tokio::test]
+    async fn test_lookup_refreshes_ahead_of_expiry() {
+        let source = InMemorySource::new();
+        source.insert_tenant("acme", "shard3");
+        let state = state_with_source("example.com", source);
+        let age = acme_ttl(&state) * state.config.refresh_ahead_percent / 100 + 1;
+        state
+            .cache
+            .insert("acme".to_string(), acme_entry(&state, "shard2", age))
+            .await;
+
+        let body = lookup_host(&state, "acme.example.com").await;
+        assert_eq!(body["shard"], "shard2");
+
+        for _ in 0..100 {
+            if !state.refreshing.lock().unwrap().contains("acme") {
+                break;
+            }
+            tokio::task::yield_now().await;
         }
-        Err(_) => Err(anyhow::anyhow!("Tenant mapping not found")),
+        let refreshed = state.cache.get("acme").await.unwrap();
+        assert_eq!(refreshed.shard, "shard3");
+        assert_eq!(refreshed.freshness(unix_now()), Freshness::Fresh);
+    }
+
+    #[tokio::test]
+    async fn test_lookup_revalidates_in_background() {
+        let source = InMemorySource::new();
+        source.insert_tenant("acme", "shard3");
+        let state = state_with_source("example.com", source);
+        state
+            .cache
+            .insert("acme".to_string(), expired_entry(&state, "shard2", 1))
+            .await;
+
+        // The stale shard is served without waiting for the source
+        let body = lookup_host(&state, "acme.example.com").await;
+        assert_eq!(body["shard"], "shard2");
+
+        for _ in 0..100 {
+            if !state.refreshing.lock().unwrap().contains("acme") {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(state.cache.get("acme").await.unwrap().shard, "shard3");
+    }
+
+    #[tokio::test]
+    async fn test_lookup_serves_stale_on_error() {
+        let mut state = test_state("example.com");
+        // Reads under a file fail with an I/O error rather than "not found"
+        state.source = Arc::new(LocalDirSource::new("/dev/null"));
+        let revalidate = state.config.stale_while_revalidate_seconds;
+
+        state
+            .cache
+            .insert("acme".to_string(), expired_entry(&state, "shard2", revalidate + 1))
+            .await;
+        let body = lookup_host(&state, "acme.example.com").await;
+        assert_eq!(body["shard"], "shard2");
+        assert_eq!(body["outcome"], "backend_error");
+        assert_eq!(body["stale"], true);
+
+        // Served up to the second before the hard expiry
+        let max_stale = state.config.max_stale_seconds;
+        state
+            .cache
+            .insert("acme".to_string(), expired_entry(&state, "shard2", max_stale - 1))
+            .await;
+        let body = lookup_host(&state, "acme.example.com").await;
+        assert_eq!(body["shard"], "shard2");
+        assert_eq!(body["stale"], true);
+
+        // But not at the hard expiry
+        state
+            .cache
+            .insert("acme".to_string(), expired_entry(&state, "shard2", max_stale))
+            .await;
+        let body = lookup_host(&state, "acme.example.com").await;
+        assert_eq!(body["shard"], state.config.default_shard);
+    }
+
+    #[tokio::test]
+    async fn test_lookup_reports_outcomes() {
+        let source = InMemorySource::new();
+        source.insert_tenant("acme", "shard2");
+        source.insert_tenant("broken", r#"{"shard": ""}"#);
+        let state = state_with_source("example.com", source);
+
+        let body = lookup_host(&state, "acme.example.com").await;
+        assert_eq!(body["shard"], "shard2");
+        assert_eq!(body["outcome"], "found");
+        assert!(body.get("stale").is_none());
+
+        let body = lookup_host(&state, "nobody.example.com").await;
+        assert_eq!(body["shard"], state.config.default_shard);
+        assert_eq!(body["outcome"], "not_found");
+
+        let body = lookup_host(&state, "broken.example.com").await;
+        assert_eq!(body["shard"], state.config.default_shard);
+        assert_eq!(body["outcome"], "invalid_record");
+
+        // Hosts without a tenant never reach the mapping store
+        let body = lookup_host(&state, "localhost").await;
+        assert!(body.get("outcome").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_backend_error_policies() {
+        let mut state = test_state("example.com");
+        state.source = Arc::new(LocalDirSource::new("/dev/null"));
+        let revalidate = state.config.stale_while_revalidate_seconds;
+        let with_stale_entry = |state: &AppState| {
+            let state = state.clone();
+            async move {
+                state
+                    .cache
+                    .insert("acme".to_string(), expired_entry(&state, "shard2", revalidate + 1))
+                    .await;
+            }
+        };
+
+        // serve_stale without a cached mapping fails open
+        let (status, body) = lookup_response(&state, "acme.example.com").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["shard"], state.config.default_shard);
+        assert_eq!(body["outcome"], "backend_error");
+        assert!(body.get("stale").is_none());
+
+        state.config.backend_error_policy = BackendErrorPolicy::FailOpen;
+        with_stale_entry(&state).await;
+        let (status, body) = lookup_response(&state, "acme.example.com").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["shard"], state.config.default_shard);
+        assert_eq!(body["outcome"], "backend_error");
+
+        state.config.backend_error_policy = BackendErrorPolicy::FailClosed;
+        with_stale_entry(&state).await;
+        let (status, body) = lookup_response(&state, "acme.example.com").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["tenant"], "acme");
+        assert_eq!(body["outcome"], "backend_error");
+
+        // A missing mapping is not a backend error
+        state.source = Arc::new(InMemorySource::new());
+        let (status, body) = lookup_response(&state, "nobody.example.com").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["outcome"], "not_found");
+    }
+
+    async fn lookup_batch_body(
+        state: &AppState,
+        request: serde_json::Value,
+    ) -> (StatusCode, serde_json::Value) {
+        let request = serde_json::from_value(request).unwrap();
+        let response = lookup_batch(State(state.clone()), Json(request)).await;
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_lookup_batch() {
+        let source = InMemorySource::new();
+        source.insert_tenant("acme", "shard2");
+        source.insert_tenant("globex", "shard3");
+        source.insert_alias("portal.customer.com", "globex");
+        let state = state_with_source("example.com", source);
+
+        // Warm the cache for acme
+        lookup_host(&state, "acme.example.com").await;
+
+        let (status, body) = lookup_batch_body(
+            &state,
+            serde_json::json!({
+                "hosts": ["acme.example.com", "portal.customer.com", "localhost"],
+                "tenants": ["nobody", "Acme!"],
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let results = body["results"].as_array().unwrap();
+        assert_eq!(results.len(), 5);
+        assert_eq!(
+            results[0],
+            serde_json::json!({
+                "host": "acme.example.com",
+                "tenant": "acme",
+                "shard": "shard2",
+                "source": "cache",
+                "outcome": "found",
+            })
+        );
+        assert_eq!(results[1]["tenant"], "globex");
+        assert_eq!(results[1]["shard"], "shard3");
+        assert_eq!(results[1]["source"], "store");
+        assert_eq!(results[2]["tenant"], serde_json::Value::Null);
+        assert_eq!(results[2]["shard"], state.config.default_shard);
+        assert_eq!(results[2]["source"], "default");
+        assert_eq!(results[3]["shard"], state.config.default_shard);
+        assert_eq!(results[3]["source"], "default");
+        assert_eq!(results[3]["outcome"], "not_found");
+        assert_eq!(results[4]["error"], "invalid tenant ID 'Acme!'");
+        assert!(results[4].get("shard").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_lookup_batch_reports_errors() {
+        let mut state = test_state("example.com");
+        state.source = Arc::new(LocalDirSource::new("/dev/null"));
+        state.config.backend_error_policy = BackendErrorPolicy::FailClosed;
+
+        let (status, body) =
+            lookup_batch_body(&state, serde_json::json!({"tenants": ["acme"]})).await;
+        assert_eq!(status, StatusCode::OK);
+        let result = &body["results"][0];
+        assert_eq!(result["outcome"], "backend_error");
+        assert!(result.get("shard").is_none());
+        assert!(result["error"].as_str().unwrap().starts_with("backend error"));
+
+        let hosts = vec!["acme.example.com"; MAX_BATCH_ITEMS + 1];
+        let (status, _) = lookup_batch_body(&state, serde_json::json!({"hosts": hosts})).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let source = InMemorySource::new();
+        source.insert_tenant("acme", "shard2");
+        let state = state_with_source("example.com", source);
+
+        lookup_host(&state, "acme.example.com").await;
+        lookup_host(&state, "acme.example.com").await;
+        lookup_host(&state, "nobody.example.com").await;
+
+        let response = export_metrics(State(state.clone())).await.into_response();
+        assert_eq!(response.headers()[CONTENT_TYPE], metrics::CONTENT_TYPE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        for line in [
+            r#"tenant_lookup_cache_hits_total{cache="mapping"} 1"#,
+            r#"tenant_lookup_cache_misses_total{cache="mapping"} 2"#,
+            r#"tenant_lookup_cache_misses_total{cache="negative"} 2"#,
+            r#"tenant_lookup_results_total{outcome="found"} 2"#,
+            r#"tenant_lookup_results_total{outcome="not_found"} 1"#,
+            r#"tenant_lookup_routed_total{shard="shard1"} 1"#,
+            r#"tenant_lookup_routed_total{shard="shard2"} 2"#,
+            r#"tenant_lookup_source_fetch_duration_seconds_count{object="mapping"} 2"#,
+            "tenant_lookup_source_fetches_in_flight 0",
+        ] {
+            assert!(body.lines().any(|l| l == line), "missing {line} in:\n{body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lookup_drops_stale_entry_for_deleted_mapping() {
+        let state = test_state("example.com");
+        let revalidate = state.config.stale_while_revalidate_seconds;
+        state
+            .cache
+            .insert("acme".to_string(), expired_entry(&state, "shard2", revalidate + 1))
+            .await;
+
+        let body = lookup_host(&state, "acme.example.com").await;
+        assert_eq!(body["shard"], state.config.default_shard);
+        assert!(state.cache.get("acme").await.is_none());
+        assert!(state.negative_cache.contains_key("acme"));
+    }
+
+    /// Counts reads and holds each result until released, failing with a
+    /// backend error once `fail` is set. Cache generation and versioned
+    /// reads, and writes, pass straight through.
+    struct GatedSource {
+        inner: InMemorySource,
+        reads: AtomicU64,
+        gate: tokio::sync::Semaphore,
+        fail: std::sync::atomic::AtomicBool,
+    }
+
+    #[async_trait::async_trait]
+    impl MappingSource for GatedSource {
+        fn describe(&self) -> String {
+            "gated".to_string()
+        }
+
+        async fn read_object(&self, object: &str) -> Result<String, SourceError> {
+            let result = self.inner.read_object(object).await;
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            let _permit = self.gate.acquire().await.unwrap();
+            if self.fail.load(Ordering::Relaxed) {
+                return Err(SourceError::Backend("unavailable".to_string()));
+            }
+            result
+        }
+
+        async fn read_generation(&self) -> Result<String, SourceError> {
+            self.inner.read_generation().await
+        }
+
+        async fn read_versioned(&self, object: &str) -> Result<VersionedObject, SourceError> {
+            self.inner.read_versioned(object).await
+        }
+
+        async fn write_object(
+            &self,
+            object: &str,
+            contents: &str,
+            precondition: Precondition,
+        ) -> Result<u64, SourceError> {
+            self.inner.write_object(object, contents, precondition).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_misses_share_one_fetch() {
+        let inner = InMemorySource::new();
+        inner.insert_tenant("acme", "shard2");
+        let source = Arc::new(GatedSource {
+            inner,
+            reads: AtomicU64::new(0),
+            gate: tokio::sync::Semaphore::new(0),
+            fail: true.into(),
+        });
+        let mut state = test_state("example.com");
+        state.source = source.clone();
+
+        let burst = |state: &AppState| {
+            (0..10)
+                .map(|_| {
+                    let state = state.clone();
+                    tokio::spawn(async move { tenant_mapping(&state, "acme").await })
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // The failed fetch is shared by the whole burst and not cached
+        let lookups = burst(&state);
+        while source.reads.load(Ordering::Relaxed) == 0 {
+            tokio::task::yield_now().await;
+        }
+        source.gate.add_permits(1);
+        for lookup in lookups {
+            let resolution = lookup.await.unwrap();
+            assert_eq!(resolution.outcome, LookupOutcome::BackendError);
+            assert!(resolution.entry.is_none());
+        }
+        assert_eq!(source.reads.load(Ordering::Relaxed), 1);
+        assert!(!state.cache.contains_key("acme"));
+        assert!(!state.negative_cache.contains_key("acme"));
+
+        // The next burst fetches again
+        source.fail.store(false, Ordering::Relaxed);
+        let lookups = burst(&state);
+        while source.reads.load(Ordering::Relaxed) == 1 {
+            tokio::task::yield_now().await;
+        }
+        source.gate.add_permits(1);
+        for lookup in lookups {
+            let resolution = lookup.await.unwrap();
+            assert_eq!(resolution.outcome, LookupOutcome::Found);
+            assert_eq!(resolution.entry.unwrap().shard, "shard2");
+        }
+        assert_eq!(source.reads.load(Ordering::Relaxed), 2);
+    }
+
+    fn admin_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer secret".parse().unwrap());
+        headers
+    }
+
+    /// Send an admin request for `tenant` with the test token and `headers`.
+    async fn admin_request(
+        state: &AppState,
+        method: &str,
+        tenant: &str,
+        headers: &[(&'static str, &str)],
+        body: &str,
+    ) -> (StatusCode, Option<String>, serde_json::Value) {
+        let mut header_map = admin_headers();
+        for (name, value) in headers {
+            header_map.insert(*name, value.parse().unwrap());
+        }
+
+        let state = State(state.clone());
+        let tenant = axum::extract::Path(tenant.to_string());
+        let response = match method {
+            "GET" => admin::get_mapping(state, tenant, header_map).await.into_response(),
+            "PUT" => admin::put_mapping(state, tenant, header_map, body.to_string())
+                .await
+                .into_response(),
+            "DELETE" => admin::delete_mapping(state, tenant, header_map)
+                .await
+                .into_response(),
+            _ => unreachable!(),
+        };
+
+        let status = response.status();
+        let etag = response
+            .headers()
+            .get("etag")
+            .map(|v| v.to_str().unwrap().to_string());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+        (status, etag, body)
+    }
+
+    #[tokio::test]
+    async fn test_admin_edits_mapping() {
+        let mut state = test_state("example.com");
+        state.admin_token = Some(Arc::from("secret"));
+        state.config.shards = ShardRegistry::from_spec("shard1, shard2, shard3").unwrap();
+
+        // Cache acme as unmapped, then create its mapping
+        lookup_host(&state, "acme.example.com").await;
+        assert!(state.negative_cache.contains_key("acme"));
+
+        let (status, _, _) = admin_request(&state, "PUT", "acme", &[], "shard2").await;
+        assert_eq!(status, StatusCode::PRECONDITION_REQUIRED);
+
+        let (status, etag, body) =
+            admin_request(&state, "PUT", "ACME", &[("if-none-match", "*")], "shard2").await;
+        assert_eq!(status, StatusCode::CREATED);
+        let etag = etag.unwrap();
+        assert_eq!(body["tenant"], "acme");
+        assert_eq!(body["mapping"]["shard"], "shard2");
+        assert!(body["mapping"]["updated_at"].is_u64());
+        assert!(!state.negative_cache.contains_key("acme"));
+        assert_eq!(lookup_host(&state, "acme.example.com").await["shard"], "shard2");
+
+        let (status, _, _) =
+            admin_request(&state, "PUT", "acme", &[("if-none-match", "*")], "shard3").await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+
+        let (status, get_etag, body) = admin_request(&state, "GET", "acme", &[], "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(get_etag.as_ref(), Some(&etag));
+        assert_eq!(body["mapping"]["shard"], "shard2");
+
+        // Unregistered shards are rejected before anything is written
+        let (status, _, body) =
+            admin_request(&state, "PUT", "acme", &[("if-match", &etag)], "shard9").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body["error"].as_str().unwrap().contains("shard9"));
+
+        // Updating replaces the cached entry
+        let (status, new_etag, _) = admin_request(
+            &state,
+            "PUT",
+            "acme",
+            &[("if-match", &etag)],
+            r#"{"shard":"shard3","metadata":{"tier":"gold"}}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let new_etag = new_etag.unwrap();
+        assert_ne!(new_etag, etag);
+        assert_eq!(lookup_host(&state, "acme.example.com").await["shard"], "shard3");
+
+        // Writes against the old version lose
+        let (status, _, _) =
+            admin_request(&state, "PUT", "acme", &[("if-match", &etag)], "shard1").await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+        let (status, _, _) = admin_request(&state, "DELETE", "acme", &[("if-match", &etag)], "").await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+
+        let (status, _, _) =
+            admin_request(&state, "DELETE", "acme", &[("if-match", &new_etag)], "").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(
+            lookup_host(&state, "acme.example.com").await["shard"],
+            state.config.default_shard
+        );
+        let (status, _, _) = admin_request(&state, "GET", "acme", &[], "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_admin_write_during_fetch_is_not_undone() {
+        let inner = InMemorySource::new();
+        inner.insert_tenant("acme", "shard1");
+        let source = Arc::new(GatedSource {
+            inner,
+            reads: AtomicU64::new(0),
+            gate: tokio::sync::Semaphore::new(0),
+            fail: false.into(),
+        });
+        let mut state = test_state("example.com");
+        state.source = source.clone();
+        state.admin_token = Some(Arc::from("secret"));
+
+        // A lookup miss, then a background refresh, reads the old mapping
+        // and is held until an admin write has replaced it
+        for (refresh, shard) in [(false, "shard2"), (true, "shard3")] {
+            let reads = source.reads.load(Ordering::Relaxed);
+            let fetch = {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    if refresh {
+                        let _ = refresh_mapping(&state, "acme").await;
+                    } else {
+                        tenant_mapping(&state, "acme").await;
+                    }
+                })
+            };
+            while source.reads.load(Ordering::Relaxed) == reads {
+                tokio::task::yield_now().await;
+            }
+
+            let (_, etag, _) = admin_request(&state, "GET", "acme", &[], "").await;
+            let (status, _, _) =
+                admin_request(&state, "PUT", "acme", &[("if-match", &etag.unwrap())], shard).await;
+            assert_eq!(status, StatusCode::OK);
+
+            source.gate.add_permits(1);
+            fetch.await.unwrap();
+            assert!(!state.cache.contains_key("acme"), "refresh = {}", refresh);
+
+            source.gate.add_permits(1);
+            assert_eq!(lookup_host(&state, "acme.example.com").await["shard"], shard);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_admin_cache_endpoints() {
+        let mut state = test_state("example.com");
+        state.admin_token = Some(Arc::from("secret"));
+        let cached = |tenant: &str, shard: &str| {
+            state
+                .config
+                .cache_entry(tenant, MappingRecord::new(shard.to_string()), unix_now() - 10)
+        };
+        state.cache.insert("acme".to_string(), cached("acme", "shard2")).await;
+        state.cache.insert("globex".to_string(), cached("globex", "shard2")).await;
+        state.cache.insert("initech".to_string(), cached("initech", "shard3")).await;
+        state.negative_cache.insert("nobody".to_string(), ()).await;
+
+        let Json(list) = admin::list_cache(State(state.clone()), admin_headers())
+            .await
+            .ok()
+            .unwrap();
+        let list = serde_json::to_value(list).unwrap();
+        let entries = list["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0]["tenant"], "acme");
+        assert_eq!(entries[0]["shard"], "shard2");
+        assert_eq!(entries[0]["age_seconds"], 10);
+        assert_eq!(entries[0]["ttl_seconds"], acme_ttl(&state) - 10);
+        assert_eq!(entries[0]["freshness"], "fresh");
+
+        let purged = |response: Result<Json<admin::PurgeResponse>, admin::AdminError>| {
+            serde_json::to_value(response.ok().unwrap().0).unwrap()["purged"].clone()
+        };
+
+        let path = |value: &str| axum::extract::Path(value.to_string());
+        let response = admin::purge_tenant(State(state.clone()), path("ACME"), admin_headers()).await;
+        assert_eq!(purged(response), 1);
+        assert!(!state.cache.contains_key("acme"));
+
+        let response = admin::purge_shard(State(state.clone()), path("Shard2"), admin_headers()).await;
+        assert_eq!(purged(response), 1);
+        assert!(!state.cache.contains_key("globex"));
+        assert!(state.cache.contains_key("initech"));
+
+        let response = admin::flush_cache(State(state.clone()), admin_headers()).await;
+        assert_eq!(purged(response), 2);
+        assert!(!state.cache.contains_key("initech"));
+        assert!(!state.negative_cache.contains_key("nobody"));
+
+        let response = admin::list_cache(State(state.clone()), HeaderMap::new()).await;
+        assert_eq!(response.err().unwrap().into_response().status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_admin_purge_during_fetch_is_not_undone() {
+        let inner = InMemorySource::new();
+        inner.insert_tenant("acme", "shard2");
+        let source = Arc::new(GatedSource {
+            inner,
+            reads: AtomicU64::new(0),
+            gate: tokio::sync::Semaphore::new(0),
+            fail: false.into(),
+        });
+        let mut state = test_state("example.com");
+        state.source = source.clone();
+        state.admin_token = Some(Arc::from("secret"));
+
+        // A lookup miss reads the mapping and is held until the cache has
+        // been purged
+        for flush in [false, true] {
+            let reads = source.reads.load(Ordering::Relaxed);
+            let lookup = {
+                let state = state.clone();
+                tokio::spawn(async move { tenant_mapping(&state, "acme").await })
+            };
+            while source.reads.load(Ordering::Relaxed) == reads {
+                tokio::task::yield_now().await;
+            }
+
+            let response = if flush {
+                admin::flush_cache(State(state.clone()), admin_headers()).await
+            } else {
+                let shard = axum::extract::Path("shard2".to_string());
+                admin::purge_shard(State(state.clone()), shard, admin_headers()).await
+            };
+            assert!(response.is_ok());
+
+            source.gate.add_permits(1);
+            lookup.await.unwrap();
+            assert!(!state.cache.contains_key("acme"), "flush = {}", flush);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_admin_requires_token() {
+        let mut state = test_state("example.com");
+        let (status, _, _) = admin_request(&state, "GET", "acme", &[], "").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        state.admin_token = Some(Arc::from("other"));
+        let (status, _, _) = admin_request(&state, "GET", "acme", &[], "").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        state.admin_token = Some(Arc::from("secret"));
+        let (status, _, body) = admin_request(&state, "GET", "acme!", &[], "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "invalid tenant ID 'acme!'");
+    }
+
+    #[tokio::test]
+    async fn test_readiness() {
+        let mut state = test_state("example.com");
+        let response = readiness(State(state.clone())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        state.source = Arc::new(LocalDirSource::new("/dev/null"));
+        let response = readiness(State(state)).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["ready"], false);
+        assert_eq!(body["components"]["mapping_store"]["ready"], false);
+        assert!(body["components"]["mapping_store"]["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("backend error"));
+    }
+
+    #[tokio::test]
+    async fn test_prewarm() {
+        let source = InMemorySource::new();
+        source.insert_tenant("acme", "shard2");
+        source.insert_tenant("globex", "shard3");
+        source.insert_alias("portal.customer.com", "globex");
+        let mut state = state_with_source("example.com", source);
+        state.config.prewarm = PrewarmMode::Bucket;
+
+        *state.prewarm.lock().unwrap() = Some(ComponentStatus::failing("pre-warming the cache"));
+        let response = readiness(State(state.clone())).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        start_prewarm(&state).await.unwrap();
+        assert_eq!(state.cache.get("acme").await.unwrap().shard, "shard2");
+        assert_eq!(state.cache.get("globex").await.unwrap().shard, "shard3");
+        let response = readiness(State(state.clone())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // A hot-tenant list only loads those tenants, remembering missing ones
+        state.cache.invalidate_all();
+        state.config.prewarm = PrewarmMode::Tenants;
+        state.config.prewarm_tenants = vec!["ACME".to_string(), "nobody".to_string()];
+        assert_eq!(prewarm_cache(&state).await, Ok((1, 2)));
+        assert!(state.cache.contains_key("acme"));
+        assert!(!state.cache.contains_key("globex"));
+        assert!(state.negative_cache.contains_key("nobody"));
+
+        // No more tenants are fetched than the cache holds
+        state.config.prewarm_tenants = (0..150).map(|i| format!("tenant{}", i)).collect();
+        assert_eq!(prewarm_cache(&state).await, Ok((0, 100)));
+
+        // A failed listing keeps the cache not ready, with the error
+        state.config.prewarm = PrewarmMode::Bucket;
+        state.source = Arc::new(LocalDirSource::new("/dev/null"));
+        let prewarm = start_prewarm(&state);
+        let status = loop {
+            let status = state.prewarm.lock().unwrap().clone().unwrap();
+            if status.error.as_deref() != Some("pre-warming the cache") {
+                break status;
+            }
+            tokio::task::yield_now().await;
+        };
+        prewarm.abort();
+        assert!(!status.ready);
+        assert!(status.error.unwrap().starts_with("failed to list tenants: backend error"));
+    }
+
+    #[tokio::test]
+    async fn test_generation_bump_invalidates_caches() {
+        let source = Arc::new(InMemorySource::new());
+        let mut state = test_state("example.com");
+        state.source = source.clone();
+
+        let fill = |state: &AppState| {
+            let state = state.clone();
+            async move {
+                state
+                    .cache
+                    .insert("acme".to_string(), acme_entry(&state, "shard2", 0))
+                    .await;
+                state.negative_cache.insert("nobody".to_string(), ()).await;
+            }
+        };
+
+        // Unchanged generation keeps the caches
+        fill(&state).await;
+        check_generation(&state).await;
+        assert!(state.cache.contains_key("acme"));
+
+        source.set_generation(2);
+        check_generation(&state).await;
+        assert!(!state.cache.contains_key("acme"));
+        assert!(!state.negative_cache.contains_key("nobody"));
+        assert_eq!(state.generation.load(Ordering::Relaxed), 2);
+
+        fill(&state).await;
+        check_generation(&state).await;
+        assert!(state.cache.contains_key("acme"));
+    }
+
+    #[tokio::test]
+    async fn test_generation_bump_during_fetch_is_not_undone() {
+        let inner = InMemorySource::new();
+        inner.insert_tenant("acme", "shard2");
+        let source = Arc::new(GatedSource {
+            inner,
+            reads: AtomicU64::new(0),
+            gate: tokio::sync::Semaphore::new(0),
+            fail: false.into(),
+        });
+        let mut state = test_state("example.com");
+        state.source = source.clone();
+
+        // The lookup reads the mapping, then the generation is bumped
+        // before the read returns
+        let lookup = {
+            let state = state.clone();
+            tokio::spawn(async move { tenant_mapping(&state, "acme").await })
+        };
+        while source.reads.load(Ordering::Relaxed) == 0 {
+            tokio::task::yield_now().await;
+        }
+        source.inner.set_generation(2);
+        check_generation(&state).await;
+
+        source.gate.add_permits(1);
+        assert_eq!(lookup.await.unwrap().entry.unwrap().shard, "shard2");
+        assert!(!state.cache.contains_key("acme"));
     }
 }
@@ -0,0 +1,194 @@
+//! Prometheus metrics served on `/metrics`.
+
+use std::sync::Arc;
+
+use moka::notification::RemovalCause;
+use prometheus::{
+    core::Collector, Encoder, HistogramOpts, HistogramTimer, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+/// Content type of the text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// The service's metrics, kept in their own registry.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    cache_hits: IntCounterVec,
+    cache_misses: IntCounterVec,
+    cache_evictions: IntCounterVec,
+    cache_entries: IntGaugeVec,
+    fetch_duration: HistogramVec,
+    fetches_in_flight: IntGauge,
+    lookups: IntCounterVec,
+    routed: IntCounterVec,
+    /// Mappings rejected because they route to a shard missing from the registry
+    pub unknown_shard_mappings: IntCounter,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        Self {
+            cache_hits: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("tenant_lookup_cache_hits_total", "Lookups answered from a cache"),
+                    &["cache"],
+                ),
+            ),
+            cache_misses: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "tenant_lookup_cache_misses_total",
+                        "Lookups that had to go to the mapping store",
+                    ),
+                    &["cache"],
+                ),
+            ),
+            cache_evictions: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "tenant_lookup_cache_evictions_total",
+                        "Entries dropped by a cache because they expired or it was full",
+                    ),
+                    &["cache", "cause"],
+                ),
+            ),
+            cache_entries: register(
+                &registry,
+                IntGaugeVec::new(
+                    Opts::new("tenant_lookup_cache_entries", "Entries held in a cache"),
+                    &["cache"],
+                ),
+            ),
+            fetch_duration: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new(
+                        "tenant_lookup_source_fetch_duration_seconds",
+                        "Time taken to read an object from the mapping store",
+                    ),
+                    &["object"],
+                ),
+            ),
+            fetches_in_flight: register(
+                &registry,
+                IntGauge::new(
+                    "tenant_lookup_source_fetches_in_flight",
+                    "Mapping store reads in progress",
+                ),
+            ),
+            lookups: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("tenant_lookup_results_total", "Lookups answered, by outcome"),
+                    &["outcome"],
+                ),
+            ),
+            routed: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("tenant_lookup_routed_total", "Lookups answered, by shard"),
+                    &["shard"],
+                ),
+            ),
+            unknown_shard_mappings: register(
+                &registry,
+                IntCounter::new(
+                    "tenant_lookup_unknown_shard_mappings_total",
+                    "Mappings rejected for routing to a shard missing from the registry",
+                ),
+            ),
+            registry,
+        }
+    }
+
+    pub fn cache_hit(&self, cache: &str) {
+        self.cache_hits.with_label_values(&[cache]).inc();
+    }
+
+    pub fn cache_miss(&self, cache: &str) {
+        self.cache_misses.with_label_values(&[cache]).inc();
+    }
+
+    /// Listener for a moka cache that counts its evictions.
+    pub fn eviction_listener<K, V>(
+        &self,
+        cache: &'static str,
+    ) -> impl Fn(Arc<K>, V, RemovalCause) + Send + Sync + 'static {
+        let metrics = self.clone();
+        move |_key, _value, cause| metrics.cache_removal(cache, cause)
+    }
+
+    /// Count an entry removed from `cache`, if the cache dropped it itself
+    /// rather than it being replaced or invalidated.
+    fn cache_removal(&self, cache: &str, cause: RemovalCause) {
+        let cause = match cause {
+            RemovalCause::Expired => "expired",
+            RemovalCause::Size => "size",
+            RemovalCause::Explicit | RemovalCause::Replaced => return,
+        };
+        self.cache_evictions.with_label_values(&[cache, cause]).inc();
+    }
+
+    pub fn set_cache_entries(&self, cache: &str, entries: u64) {
+        self.cache_entries
+            .with_label_values(&[cache])
+            .set(entries.try_into().unwrap_or(i64::MAX));
+    }
+
+    /// Track a read of an `object` kind from the mapping store until the
+    /// returned guard is dropped.
+    pub fn start_fetch(&self, object: &str) -> FetchTimer {
+        self.fetches_in_flight.inc();
+        FetchTimer {
+            in_flight: self.fetches_in_flight.clone(),
+            _timer: self.fetch_duration.with_label_values(&[object]).start_timer(),
+        }
+    }
+
+    /// Count a lookup answered with `shard`. `outcome` is a
+    /// `LookupOutcome`, or `no_tenant` for hosts that name no tenant.
+    pub fn lookup(&self, outcome: &str, shard: Option<&str>) {
+        self.lookups.with_label_values(&[outcome]).inc();
+        if let Some(shard) = shard {
+            self.routed.with_label_values(&[shard]).inc();
+        }
+    }
+
+    /// The metrics in the text exposition format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        // Encoding into a Vec only fails on malformed metrics
+        let _ = TextEncoder::new().encode(&self.registry.gather(), &mut buffer);
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+/// Times a mapping store read and counts it as in flight while alive.
+pub struct FetchTimer {
+    in_flight: IntGauge,
+    _timer: HistogramTimer,
+}
+
+impl Drop for FetchTimer {
+    fn drop(&mut self) {
+        self.in_flight.dec();
+    }
+}
+
+fn register<T: Collector + Clone + 'static>(
+    registry: &Registry,
+    metric: prometheus::Result<T>,
+) -> T {
+    let metric = metric.expect("metric options are valid");
+    registry
+        .register(Box::new(metric.clone()))
+        .expect("metric names are unique");
+    metric
+}
@@ -0,0 +1,82 @@
+//! Vanity domains that route as a tenant, e.g. `portal.customer.com` as
+//! tenant `customer`.
+//!
+//! Each alias is an object named `_aliases/<host>` next to the tenant
+//! mappings, containing the tenant name either as bare text or as
+//! `{"tenant":"customer"}`. Routers look the host up there before falling
+//! back to extracting the tenant from its labels.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use serde::{Deserialize, Serialize};
+
+use crate::mapping::MappingError;
+use crate::tenant::{canonical_tenant_id, parse_authority, Host};
+
+/// Object name prefix (directory) holding alias objects.
+pub const ALIAS_OBJECT_PREFIX: &str = "_aliases";
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AliasRecord {
+    pub tenant: String,
+}
+
+/// Host an authority is looked up under: lowercase ASCII, without port or
+/// trailing dot. IP literals never have aliases.
+///
+/// # Example
+/// ```
+/// use tenant_routing_core::alias::alias_host;
+/// assert_eq!(alias_host("Portal.Customer.com.:443").as_deref(), Some("portal.customer.com"));
+/// assert_eq!(alias_host("10.0.0.1"), None);
+/// ```
+pub fn alias_host(authority: &str) -> Option<String> {
+    match parse_authority(authority)? {
+        Host::Domain(host) => Some(host),
+        Host::Ip(_) => None,
+    }
+}
+
+/// Build the object name for a host's alias (without leading slash)
+///
+/// # Example
+/// ```
+/// use tenant_routing_core::alias::build_alias_object_name;
+/// assert_eq!(build_alias_object_name("portal.customer.com"), "_aliases/portal.customer.com");
+/// ```
+pub fn build_alias_object_name(host: &str) -> String {
+    format!("{}/{}", ALIAS_OBJECT_PREFIX, host)
+}
+
+/// Build GCS path for a host's alias
+///
+/// # Example
+/// ```
+/// use tenant_routing_core::alias::build_alias_path;
+/// assert_eq!(build_alias_path("my-bucket", "portal.customer.com"), "/my-bucket/_aliases/portal.customer.com");
+/// ```
+pub fn build_alias_path(bucket: &str, host: &str) -> String {
+    format!("/{}/{}", bucket, build_alias_object_name(host))
+}
+
+/// Parse the raw contents of an alias object into a canonical tenant ID.
+///
+/// # Examples
+/// ```
+/// use tenant_routing_core::alias::parse_alias_record;
+/// assert_eq!(parse_alias_record("Customer\n").unwrap(), "customer");
+/// assert_eq!(parse_alias_record(r#"{"tenant":"münchen"}"#).unwrap(), "xn--mnchen-3ya");
+/// ```
+pub fn parse_alias_record(raw: &str) -> Result<String, MappingError> {
+    let raw = raw.trim();
+
+    let tenant = if raw.starts_with('{') {
+        let record: AliasRecord =
+            serde_json::from_str(raw).map_err(|e| MappingError::InvalidJson(e.to_string()))?;
+        record.tenant
+    } else {
+        raw.to_string()
+    };
+
+    canonical_tenant_id(&tenant).ok_or(MappingError::InvalidTenant(tenant))
+}
@@ -1,34 +1,363 @@
 use alloc::format;
 use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 use serde::{Deserialize, Serialize};
 
+use crate::hash::stable_hash;
+use crate::mapping::{
+    route_mapping, select_weighted_shard, MappingError, MappingRecord, WeightedShard,
+};
+use crate::migration::{MigrationState, RequestContext, RouteDecision};
+
+/// How usable a cached mapping is at a given time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Freshness {
+    /// Before the soft expiry.
+    Fresh,
+    /// Before the soft expiry but past the refresh-ahead point: serve it and
+    /// refresh it in the background.
+    RefreshAhead,
+    /// Within the stale-while-revalidate window: serve it and refresh it in
+    /// the background.
+    Revalidate,
+    /// Before the hard expiry: refresh it first, but keep serving it if the
+    /// refresh fails.
+    Stale,
+    /// Past the hard expiry.
+    Expired,
+}
+
+impl Freshness {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Freshness::Fresh => "fresh",
+            Freshness::RefreshAhead => "refresh_ahead",
+            Freshness::Revalidate => "revalidate",
+            Freshness::Stale => "stale",
+            Freshness::Expired => "expired",
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct CacheEntry {
     pub shard: String,
+    /// Soft expiry: the entry is fresh until then.
     pub expiry: u64,
+    /// When a request should start refreshing the entry ahead of its soft
+    /// expiry. 0 when it should not.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub refresh_at: u64,
+    /// End of the stale-while-revalidate window. 0 when there is none.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub revalidate_until: u64,
+    /// Hard expiry: the entry may stand in for a failed refresh until then.
+    /// 0 when it may not.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub stale_until: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub splits: Vec<WeightedShard>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub migration: Option<MigrationState>,
+    /// When the mapping was fetched. 0 when unknown.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub cached_at: u64,
+    /// Cache generation the entry was stored in; an entry from another
+    /// generation counts as missing. 0 until the first bump.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub generation: u64,
 }
 
 impl CacheEntry {
     pub fn new(shard: String, expiry: u64) -> Self {
-        Self { shard, expiry }
+        Self {
+            shard,
+            expiry,
+            refresh_at: 0,
+            revalidate_until: 0,
+            stale_until: 0,
+            splits: Vec::new(),
+            migration: None,
+            cached_at: 0,
+            generation: 0,
+        }
+    }
+
+    pub fn from_record(record: MappingRecord, current_time: u64, ttl_seconds: u64) -> Self {
+        Self {
+            shard: record.shard,
+            expiry: current_time + ttl_seconds,
+            refresh_at: 0,
+            revalidate_until: 0,
+            stale_until: 0,
+            splits: record.splits,
+            migration: record.migration,
+            cached_at: current_time,
+            generation: 0,
+        }
+    }
+
+    /// Pick the shard for a request. See [`select_weighted_shard`].
+    pub fn select_shard(&self, tenant: &str, key: &str) -> &str {
+        select_weighted_shard(&self.shard, &self.splits, tenant, key)
+    }
+
+    /// Route a request exactly as [`MappingRecord::route`] would.
+    pub fn route(&self, ctx: &RequestContext) -> RouteDecision<'_> {
+        route_mapping(&self.shard, &self.splits, self.migration.as_ref(), ctx)
+    }
+
+    /// Whether any request can be routed to `shard` by this entry, including
+    /// through a split or either side of a migration.
+    pub fn routes_to(&self, shard: &str) -> bool {
+        self.shard == shard
+            || self.splits.iter().any(|split| split.shard == shard)
+            || self.migration.as_ref().is_some_and(|migration| {
+                migration.source_shard == shard || migration.target_shard == shard
+            })
     }
 
+    /// Whether the entry is fresh, i.e. before its soft expiry.
     pub fn is_valid(&self, current_time: u64) -> bool {
         self.expiry > current_time
     }
 
+    pub fn freshness(&self, current_time: u64) -> Freshness {
+        if self.expiry > current_time {
+            if self.refresh_at != 0 && self.refresh_at <= current_time {
+                Freshness::RefreshAhead
+            } else {
+                Freshness::Fresh
+            }
+        } else if self.revalidate_until > current_time {
+            Freshness::Revalidate
+        } else if self.stale_until > current_time {
+            Freshness::Stale
+        } else {
+            Freshness::Expired
+        }
+    }
+
+    /// Allow serving the entry past its soft expiry: while refreshing it in
+    /// the background for `stale_while_revalidate_seconds`, and in place of
+    /// failed refreshes for `max_stale_seconds`, which also caps the former.
+    ///
+    /// # Example
+    /// ```
+    /// use tenant_routing_core::cache::{CacheEntry, Freshness};
+    /// let entry = CacheEntry::with_ttl("shard1".into(), 1000, 300).with_stale_windows(60, 600);
+    /// assert_eq!(entry.freshness(1299), Freshness::Fresh);
+    /// assert_eq!(entry.freshness(1300), Freshness::Revalidate);
+    /// assert_eq!(entry.freshness(1360), Freshness::Stale);
+    /// assert_eq!(entry.freshness(1900), Freshness::Expired);
+    /// ```
+    pub fn with_stale_windows(
+        mut self,
+        stale_while_revalidate_seconds: u64,
+        max_stale_seconds: u64,
+    ) -> Self {
+        self.stale_until = self.expiry + max_stale_seconds;
+        self.revalidate_until = (self.expiry + stale_while_revalidate_seconds).min(self.stale_until);
+        self
+    }
+
+    /// Refresh the entry ahead of its soft expiry, once `current_time`
+    /// reaches `refresh_at`.
+    pub fn with_refresh_at(mut self, refresh_at: u64) -> Self {
+        self.refresh_at = refresh_at;
+        self
+    }
+
+    /// Hold off further background refreshes until `until`, e.g. while one is
+    /// in flight. The entry's expiry and stale windows are left alone.
+    pub fn postpone_refresh(&mut self, until: u64) {
+        self.refresh_at = until;
+    }
+
+    /// Whether a background refresh was claimed with [`postpone_refresh`]
+    /// and is held off past `current_time`.
+    ///
+    /// [`postpone_refresh`]: CacheEntry::postpone_refresh
+    pub fn refresh_postponed(&self, current_time: u64) -> bool {
+        self.refresh_at > current_time
+    }
+
+    /// Last time the entry can be used at all.
+    pub fn hard_expiry(&self) -> u64 {
+        self.expiry.max(self.revalidate_until).max(self.stale_until)
+    }
+
     pub fn with_ttl(shard: String, current_time: u64, ttl_seconds: u64) -> Self {
         Self {
-            shard,
+            cached_at: current_time,
+            ..Self::new(shard, current_time + ttl_seconds)
+        }
+    }
+}
+
+/// TTL for a tenant's cache entries: `ttl_seconds` less a deterministic
+/// per-tenant share of up to `jitter_percent`, so entries cached at the same
+/// time (e.g. right after a deploy) do not all expire together.
+///
+/// # Example
+/// ```
+/// use tenant_routing_core::cache::jittered_ttl;
+/// let ttl = jittered_ttl("acme", 300, 10);
+/// assert!((270..=300).contains(&ttl));
+/// assert_eq!(ttl, jittered_ttl("acme", 300, 10));
+/// assert_eq!(jittered_ttl("acme", 300, 0), 300);
+/// ```
+pub fn jittered_ttl(tenant: &str, ttl_seconds: u64, jitter_percent: u64) -> u64 {
+    let max_jitter = ttl_seconds * jitter_percent.min(100) / 100;
+    if max_jitter == 0 {
+        return ttl_seconds;
+    }
+
+    ttl_seconds - stable_hash(&["cache-ttl", tenant]) % (max_jitter + 1)
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+/// Cached result of an alias lookup. `tenant` is `None` when the host has no
+/// alias, so hosts without one are not looked up on every request.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AliasCacheEntry {
+    pub tenant: Option<String>,
+    pub expiry: u64,
+    /// Cache generation the entry was stored in; an entry from another
+    /// generation counts as missing. 0 until the first bump.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub generation: u64,
+}
+
+impl AliasCacheEntry {
+    pub fn with_ttl(tenant: Option<String>, current_time: u64, ttl_seconds: u64) -> Self {
+        Self {
+            tenant,
             expiry: current_time + ttl_seconds,
+            generation: 0,
         }
     }
+
+    pub fn is_valid(&self, current_time: u64) -> bool {
+        self.expiry > current_time
+    }
+}
+
+/// Cached "no mapping" result for a tenant, so repeated requests for unknown
+/// tenants (e.g. a scan of random subdomains) do not each read the mapping
+/// store. Uses `negative_cache_ttl_seconds`, which is kept short so newly
+/// onboarded tenants are picked up quickly.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct NegativeCacheEntry {
+    pub expiry: u64,
+    /// Cache generation the entry was stored in; an entry from another
+    /// generation counts as missing. 0 until the first bump.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub generation: u64,
+}
+
+impl NegativeCacheEntry {
+    pub fn with_ttl(current_time: u64, ttl_seconds: u64) -> Self {
+        Self {
+            expiry: current_time + ttl_seconds,
+            generation: 0,
+        }
+    }
+
+    pub fn is_valid(&self, current_time: u64) -> bool {
+        self.expiry > current_time
+    }
+}
+
+pub fn generate_negative_cache_key(tenant: &str) -> String {
+    format!("missing:{}", tenant)
+}
+
+pub fn generate_alias_cache_key(host: &str) -> String {
+    format!("alias:{}", host)
 }
 
 pub fn generate_cache_key(tenant: &str) -> String {
     format!("tenant:{}", tenant)
 }
 
+/// Tenant a mapping cache key is for.
+///
+/// # Example
+/// ```
+/// use tenant_routing_core::cache::parse_cache_key;
+/// assert_eq!(parse_cache_key("tenant:acme").as_deref(), Some("acme"));
+/// ```
 pub fn parse_cache_key(key: &str) -> Option<String> {
     key.strip_prefix("tenant:").map(|s| s.to_string())
 }
+
+/// Object holding the cache generation, as bare text (`7`) or JSON
+/// (`{"generation":7}`). Bumping it invalidates every cached mapping, alias
+/// and negative entry in both routers.
+pub const GENERATION_OBJECT: &str = "_meta/generation";
+
+/// Key the WASM filter caches the current generation under.
+pub const GENERATION_CACHE_KEY: &str = "meta:generation";
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GenerationRecord {
+    pub generation: u64,
+}
+
+/// Build GCS path for the cache generation object
+///
+/// # Example
+/// ```
+/// use tenant_routing_core::cache::build_generation_path;
+/// assert_eq!(build_generation_path("my-bucket"), "/my-bucket/_meta/generation");
+/// ```
+pub fn build_generation_path(bucket: &str) -> String {
+    format!("/{}/{}", bucket, GENERATION_OBJECT)
+}
+
+/// Parse the raw contents of the generation object.
+///
+/// # Examples
+/// ```
+/// use tenant_routing_core::cache::parse_generation_record;
+/// assert_eq!(parse_generation_record("7\n"), Ok(7));
+/// assert_eq!(parse_generation_record(r#"{"generation":8}"#), Ok(8));
+/// assert!(parse_generation_record("seven").is_err());
+/// ```
+pub fn parse_generation_record(raw: &str) -> Result<u64, MappingError> {
+    let raw = raw.trim();
+
+    if raw.starts_with('{') {
+        let record: GenerationRecord =
+            serde_json::from_str(raw).map_err(|e| MappingError::InvalidJson(e.to_string()))?;
+        Ok(record.generation)
+    } else {
+        raw.parse()
+            .map_err(|_| MappingError::InvalidGeneration(raw.to_string()))
+    }
+}
+
+/// Cached cache generation, re-read from [`GENERATION_OBJECT`] after
+/// `expiry`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GenerationCacheEntry {
+    pub generation: u64,
+    pub expiry: u64,
+}
+
+impl GenerationCacheEntry {
+    pub fn with_ttl(generation: u64, current_time: u64, ttl_seconds: u64) -> Self {
+        Self {
+            generation,
+            expiry: current_time + ttl_seconds,
+        }
+    }
+
+    pub fn is_valid(&self, current_time: u64) -> bool {
+        self.expiry > current_time
+    }
+}
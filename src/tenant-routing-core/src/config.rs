@@ -1,11 +1,182 @@
+use alloc::format;
 use alloc::string::String;
+use alloc::vec::Vec;
 use serde::{Deserialize, Serialize};
 
+use crate::cache::{jittered_ttl, CacheEntry};
+use crate::error::{ConfigError, ValidationError};
+use crate::extractor::{default_tenant_extractors, TenantExtractor, TenantRequest};
+use crate::hash::weighted_rendezvous_pick;
+use crate::mapping::{parse_mapping_record, MappingError, MappingRecord};
+use crate::registry::{deserialize_shard_name, deserialize_shard_names, ShardRegistry};
+use crate::tenant::{canonical_tenant_id, extract_tenant_for_domains, BaseDomain};
+
+/// What routers do with a request when the mapping store fails, as opposed
+/// to a tenant having no mapping.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendErrorPolicy {
+    /// Route with the cached mapping, within `max_stale_seconds`, else as if
+    /// the tenant had no mapping.
+    #[default]
+    ServeStale,
+    /// Route as if the tenant had no mapping.
+    FailOpen,
+    /// Reject the request with 503.
+    FailClosed,
+}
+
+impl BackendErrorPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackendErrorPolicy::ServeStale => "serve_stale",
+            BackendErrorPolicy::FailOpen => "fail_open",
+            BackendErrorPolicy::FailClosed => "fail_closed",
+        }
+    }
+
+    /// Whether a cached mapping past its stale-while-revalidate window may
+    /// still be served.
+    pub fn serves_stale(&self) -> bool {
+        *self == BackendErrorPolicy::ServeStale
+    }
+}
+
+/// Which tenants the lookup service loads into its cache at startup.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PrewarmMode {
+    /// Start with an empty cache.
+    #[default]
+    Off,
+    /// Every tenant with a mapping object in the bucket.
+    Bucket,
+    /// The tenants in `prewarm_tenants`.
+    Tenants,
+}
+
+impl PrewarmMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PrewarmMode::Off => "off",
+            PrewarmMode::Bucket => "bucket",
+            PrewarmMode::Tenants => "tenants",
+        }
+    }
+}
+
+/// Settings shared by every router and service.
+///
+/// This is the configuration schema: the same keys are used in the TOML or
+/// YAML file, the WASM plugin JSON and (see `loader::ENV_VARS`) environment
+/// variables. Every key is optional and unknown keys are rejected.
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct TenantRoutingConfig {
+    /// Bucket holding `<tenant>/shard` mapping objects.
     pub gcs_bucket: String,
+    /// How long mappings and aliases are cached.
     pub cache_ttl_seconds: u64,
+    /// Up to this percentage of `cache_ttl_seconds` is taken off each
+    /// tenant's TTL, deterministically per tenant, so mappings cached together
+    /// do not expire together.
+    #[serde(default = "default_cache_ttl_jitter_percent")]
+    pub cache_ttl_jitter_percent: u64,
+    /// Percentage of a mapping's TTL after which a request for the tenant
+    /// refreshes it in the background, so tenants in use are reloaded before
+    /// they expire. 0 disables refresh-ahead.
+    #[serde(default = "default_refresh_ahead_percent")]
+    pub refresh_ahead_percent: u64,
+    /// How long past `cache_ttl_seconds` a cached mapping is still served
+    /// while it is refreshed in the background.
+    #[serde(default = "default_stale_while_revalidate_seconds")]
+    pub stale_while_revalidate_seconds: u64,
+    /// How long past `cache_ttl_seconds` a cached mapping is served when
+    /// refreshing it fails, e.g. while the bucket is unreachable.
+    #[serde(default = "default_max_stale_seconds")]
+    pub max_stale_seconds: u64,
+    /// How often routers re-read the cache generation (see
+    /// `cache::GENERATION_OBJECT`). 0 disables generations.
+    #[serde(default = "default_generation_check_seconds")]
+    pub generation_check_seconds: u64,
+    /// How long a tenant without a mapping is remembered as such. 0 disables
+    /// negative caching.
+    #[serde(default = "default_negative_cache_ttl_seconds")]
+    pub negative_cache_ttl_seconds: u64,
+    /// What to do when the mapping store fails.
+    pub backend_error_policy: BackendErrorPolicy,
+    /// Shard for tenants without a mapping, unless `fallback_shards` is set.
+    #[serde(deserialize_with = "deserialize_shard_name")]
     pub default_shard: String,
+    /// Shards that tenants without a mapping are spread over. When empty,
+    /// every unmapped tenant goes to `default_shard`.
+    #[serde(default, deserialize_with = "deserialize_shard_names")]
+    pub fallback_shards: Vec<String>,
+    /// Shards mappings may route to. Mappings naming any other shard are
+    /// rejected. Empty disables the check.
+    #[serde(default)]
+    pub shards: ShardRegistry,
+    /// Request header whose value keeps a user on one shard when a tenant's
+    /// traffic is split across several (see `mapping::WeightedShard`).
+    #[serde(default = "default_split_key_header")]
+    pub split_key_header: String,
+    /// Domains tenants are served under. When empty, the tenant is the first
+    /// label of any host with at least two labels.
+    #[serde(default)]
+    pub base_domains: Vec<BaseDomain>,
+    /// How requests are matched to tenants, tried in order. Defaults to the
+    /// host alone.
+    #[serde(default = "default_tenant_extractors")]
+    pub tenant_extractors: Vec<TenantExtractor>,
+    /// Port the lookup service and GCS proxy listen on. The WASM filter
+    /// ignores it.
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// Which tenants the lookup service caches before reporting ready. The
+    /// WASM filter ignores it.
+    pub prewarm: PrewarmMode,
+    /// Hot tenants to cache when `prewarm` is `tenants`.
+    #[serde(default)]
+    pub prewarm_tenants: Vec<String>,
+    /// How many mappings pre-warming fetches at once.
+    #[serde(default = "default_prewarm_concurrency")]
+    pub prewarm_concurrency: u64,
+}
+
+fn default_cache_ttl_jitter_percent() -> u64 {
+    10
+}
+
+fn default_refresh_ahead_percent() -> u64 {
+    80
+}
+
+fn default_stale_while_revalidate_seconds() -> u64 {
+    60
+}
+
+fn default_max_stale_seconds() -> u64 {
+    3600
+}
+
+fn default_generation_check_seconds() -> u64 {
+    10
+}
+
+fn default_negative_cache_ttl_seconds() -> u64 {
+    30
+}
+
+fn default_port() -> u16 {
+    8080
+}
+
+fn default_prewarm_concurrency() -> u64 {
+    16
+}
+
+fn default_split_key_header() -> String {
+    String::from("x-session-id")
 }
 
 impl Default for TenantRoutingConfig {
@@ -13,7 +184,23 @@ impl Default for TenantRoutingConfig {
         Self {
             gcs_bucket: String::from("tenant-shard-mapping"),
             cache_ttl_seconds: 300,
+            cache_ttl_jitter_percent: default_cache_ttl_jitter_percent(),
+            refresh_ahead_percent: default_refresh_ahead_percent(),
+            stale_while_revalidate_seconds: default_stale_while_revalidate_seconds(),
+            max_stale_seconds: default_max_stale_seconds(),
+            generation_check_seconds: default_generation_check_seconds(),
+            negative_cache_ttl_seconds: default_negative_cache_ttl_seconds(),
+            backend_error_policy: BackendErrorPolicy::default(),
             default_shard: String::from("shard1"),
+            fallback_shards: Vec::new(),
+            shards: ShardRegistry::default(),
+            split_key_header: default_split_key_header(),
+            base_domains: Vec::new(),
+            tenant_extractors: default_tenant_extractors(),
+            port: default_port(),
+            prewarm: PrewarmMode::default(),
+            prewarm_tenants: Vec::new(),
+            prewarm_concurrency: default_prewarm_concurrency(),
         }
     }
 }
@@ -23,20 +210,172 @@ impl TenantRoutingConfig {
         Self {
             gcs_bucket,
             cache_ttl_seconds,
+            cache_ttl_jitter_percent: default_cache_ttl_jitter_percent(),
+            refresh_ahead_percent: default_refresh_ahead_percent(),
+            stale_while_revalidate_seconds: default_stale_while_revalidate_seconds(),
+            max_stale_seconds: default_max_stale_seconds(),
+            generation_check_seconds: default_generation_check_seconds(),
+            negative_cache_ttl_seconds: default_negative_cache_ttl_seconds(),
+            backend_error_policy: BackendErrorPolicy::default(),
             default_shard,
+            fallback_shards: Vec::new(),
+            shards: ShardRegistry::default(),
+            split_key_header: default_split_key_header(),
+            base_domains: Vec::new(),
+            tenant_extractors: default_tenant_extractors(),
+            port: default_port(),
+            prewarm: PrewarmMode::default(),
+            prewarm_tenants: Vec::new(),
+            prewarm_concurrency: default_prewarm_concurrency(),
         }
     }
 
-    pub fn validate(&self) -> Result<(), &'static str> {
+    /// Check every field, reporting all problems rather than the first.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut errors = ConfigError::default();
+
         if self.gcs_bucket.is_empty() {
-            return Err("GCS bucket name cannot be empty");
+            errors.push("gcs_bucket", ValidationError::Empty);
+        }
+        if self.cache_ttl_seconds == 0 {
+            errors.push("cache_ttl_seconds", ValidationError::Zero);
+        }
+        if self.cache_ttl_jitter_percent > 100 {
+            errors.push("cache_ttl_jitter_percent", ValidationError::AboveMaximum(100));
+        }
+        if self.refresh_ahead_percent > 100 {
+            errors.push("refresh_ahead_percent", ValidationError::AboveMaximum(100));
         }
         if self.default_shard.is_empty() {
-            return Err("Default shard cannot be empty");
+            errors.push("default_shard", ValidationError::Empty);
+        } else if !self.shards.contains(&self.default_shard) {
+            errors.push(
+                "default_shard",
+                ValidationError::UnknownShard(self.default_shard.clone()),
+            );
         }
-        if self.cache_ttl_seconds == 0 {
-            return Err("Cache TTL must be greater than 0");
+        for (i, shard) in self.fallback_shards.iter().enumerate() {
+            if shard.is_empty() {
+                errors.push(format!("fallback_shards[{}]", i), ValidationError::Empty);
+            } else if !self.shards.contains(shard) {
+                errors.push(
+                    format!("fallback_shards[{}]", i),
+                    ValidationError::UnknownShard(shard.clone()),
+                );
+            }
+        }
+        self.shards.collect_errors("shards", &mut errors);
+        if self.split_key_header.is_empty() {
+            errors.push("split_key_header", ValidationError::Empty);
+        }
+        for (i, domain) in self.base_domains.iter().enumerate() {
+            if domain.domain.is_empty() {
+                errors.push(format!("base_domains[{}].domain", i), ValidationError::Empty);
+            }
+            if domain.tenant_labels == 0 {
+                errors.push(
+                    format!("base_domains[{}].tenant_labels", i),
+                    ValidationError::Zero,
+                );
+            }
+        }
+        if self.tenant_extractors.is_empty() {
+            errors.push("tenant_extractors", ValidationError::Empty);
+        }
+        for (i, extractor) in self.tenant_extractors.iter().enumerate() {
+            extractor.collect_errors(&format!("tenant_extractors[{}]", i), &mut errors);
+        }
+        if self.port == 0 {
+            errors.push("port", ValidationError::Zero);
+        }
+        if self.prewarm == PrewarmMode::Tenants && self.prewarm_tenants.is_empty() {
+            errors.push("prewarm_tenants", ValidationError::Empty);
+        }
+        for (i, tenant) in self.prewarm_tenants.iter().enumerate() {
+            if canonical_tenant_id(tenant).is_none() {
+                errors.push(
+                    format!("prewarm_tenants[{}]", i),
+                    ValidationError::InvalidTenant(tenant.clone()),
+                );
+            }
+        }
+        if self.prewarm_concurrency == 0 {
+            errors.push("prewarm_concurrency", ValidationError::Zero);
+        }
+
+        errors.into_result()
+    }
+
+    /// Tenant named by a request's host. See
+    /// [`extract_tenant_for_domains`](crate::tenant::extract_tenant_for_domains).
+    pub fn extract_tenant(&self, host: &str) -> Option<String> {
+        extract_tenant_for_domains(host, &self.base_domains)
+    }
+
+    /// Tenant for a request: the first match among `tenant_extractors`.
+    pub fn extract_request_tenant(&self, request: &TenantRequest) -> Option<String> {
+        self.tenant_extractors
+            .iter()
+            .find_map(|extractor| match extractor {
+                TenantExtractor::Host => request
+                    .alias
+                    .clone()
+                    .or_else(|| self.extract_tenant(request.host)),
+                _ => extractor.extract(request),
+            })
+    }
+
+    /// Whether the host's alias can decide the tenant, i.e. the host
+    /// extractor is configured and no extractor ahead of it matches. Routers
+    /// only look aliases up in that case.
+    pub fn needs_alias(&self, request: &TenantRequest) -> bool {
+        for extractor in &self.tenant_extractors {
+            match extractor {
+                TenantExtractor::Host => return true,
+                _ if extractor.extract(request).is_some() => return false,
+                _ => {}
+            }
         }
-        Ok(())
+        false
+    }
+
+    pub fn uses_hash_fallback(&self) -> bool {
+        !self.fallback_shards.is_empty()
+    }
+
+    /// Shard for a tenant that has no mapping.
+    ///
+    /// With `fallback_shards` configured the tenant is placed with rendezvous
+    /// hashing, weighted by each shard's registry capacity, so unmapped
+    /// tenants spread evenly and adding a shard only moves the tenants that
+    /// land on the new one. Otherwise this is `default_shard`.
+    pub fn fallback_shard(&self, tenant: &str) -> &str {
+        let candidates = self
+            .fallback_shards
+            .iter()
+            .map(|s| (s.as_str(), self.shards.capacity_weight(s)));
+
+        weighted_rendezvous_pick(tenant, candidates).unwrap_or(&self.default_shard)
+    }
+
+    /// Cache entry for a freshly fetched mapping of `tenant`, with the
+    /// tenant's jittered TTL, refresh-ahead point and the stale windows.
+    pub fn cache_entry(&self, tenant: &str, record: MappingRecord, current_time: u64) -> CacheEntry {
+        let ttl = jittered_ttl(tenant, self.cache_ttl_seconds, self.cache_ttl_jitter_percent);
+        let refresh_at = match self.refresh_ahead_percent {
+            0 => 0,
+            percent => current_time + ttl * percent.min(100) / 100,
+        };
+
+        CacheEntry::from_record(record, current_time, ttl)
+            .with_refresh_at(refresh_at)
+            .with_stale_windows(self.stale_while_revalidate_seconds, self.max_stale_seconds)
+    }
+
+    /// Parse a mapping object and check it only routes to registered shards.
+    pub fn parse_mapping(&self, raw: &str) -> Result<MappingRecord, MappingError> {
+        let record = parse_mapping_record(raw)?;
+        self.shards.validate_record(&record)?;
+        Ok(record)
     }
 }
@@ -0,0 +1,141 @@
+//! Errors for invalid configuration.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// What is wrong with one configuration value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The value must be set.
+    Empty,
+    /// The value must be greater than 0.
+    Zero,
+    /// The value is larger than the given maximum.
+    AboveMaximum(u64),
+    /// The shard is missing from the shard registry.
+    UnknownShard(String),
+    /// The shard is registered more than once.
+    DuplicateShard(String),
+    /// A failover names its own shard or one missing from the registry.
+    InvalidFailover(String),
+    /// A path prefix does not start and end with `/`.
+    InvalidPathPrefix(String),
+    /// The value is not a valid tenant ID.
+    InvalidTenant(String),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::Empty => write!(f, "cannot be empty"),
+            ValidationError::Zero => write!(f, "must be greater than 0"),
+            ValidationError::AboveMaximum(max) => write!(f, "must be at most {}", max),
+            ValidationError::UnknownShard(shard) => {
+                write!(f, "shard '{}' is not in the shard registry", shard)
+            }
+            ValidationError::DuplicateShard(shard) => {
+                write!(f, "shard '{}' is registered more than once", shard)
+            }
+            ValidationError::InvalidFailover(shard) => {
+                write!(f, "failover '{}' must name another registered shard", shard)
+            }
+            ValidationError::InvalidPathPrefix(prefix) => {
+                write!(f, "path prefix '{}' must start and end with '/'", prefix)
+            }
+            ValidationError::InvalidTenant(tenant) => {
+                write!(f, "'{}' is not a valid tenant ID", tenant)
+            }
+        }
+    }
+}
+
+/// A validation failure and the path of the field it applies to, e.g.
+/// `shards[2].failover`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldError {
+    pub field: String,
+    pub error: ValidationError,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, error: ValidationError) -> Self {
+        Self {
+            field: field.into(),
+            error,
+        }
+    }
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.field, self.error)
+    }
+}
+
+/// Every problem found while validating a configuration.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConfigError {
+    errors: Vec<FieldError>,
+}
+
+impl ConfigError {
+    pub fn errors(&self) -> &[FieldError] {
+        &self.errors
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub(crate) fn push(&mut self, field: impl Into<String>, error: ValidationError) {
+        self.errors.push(FieldError::new(field, error));
+    }
+
+    /// `Ok` if nothing was collected.
+    pub(crate) fn into_result(self) -> Result<(), ConfigError> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration: ")?;
+        for (i, error) in self.errors.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ConfigError {}
+
+/// Why a configuration could not be loaded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoadError {
+    /// A layer could not be read or parsed, or does not match the schema.
+    /// `origin` names the file, environment variable or other layer.
+    Layer { origin: String, message: String },
+    /// The merged configuration failed validation.
+    Invalid(ConfigError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Layer { origin, message } => write!(f, "{}: {}", origin, message),
+            LoadError::Invalid(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for LoadError {}
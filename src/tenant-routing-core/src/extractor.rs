@@ -0,0 +1,221 @@
+//! Ways of identifying the tenant a request belongs to.
+//!
+//! Routers try the configured extractors in order and use the first tenant
+//! found. The default is the host alone, which suits tenants on their own
+//! subdomain; clients behind a shared hostname can be identified by path,
+//! header or token instead.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{ConfigError, ValidationError};
+use crate::tenant::canonical_tenant_id;
+
+/// One way of identifying the tenant from a request.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TenantExtractor {
+    /// The host's alias (see `crate::alias`), else its labels.
+    Host,
+    /// The path segment after `prefix`, e.g. `acme` in `/t/acme/orders`.
+    PathPrefix {
+        #[serde(default = "default_path_prefix")]
+        prefix: String,
+    },
+    /// A request header set by a trusted upstream, e.g. `x-tenant-id`. The
+    /// proxy in front of the router must strip it from client requests.
+    Header {
+        #[serde(default = "default_tenant_header")]
+        name: String,
+    },
+    /// A claim in the `Authorization: Bearer` JWT. The signature is NOT
+    /// verified, so backends must still authenticate the token.
+    JwtClaim {
+        #[serde(default = "default_jwt_claim")]
+        claim: String,
+    },
+}
+
+fn default_path_prefix() -> String {
+    String::from("/t/")
+}
+
+fn default_tenant_header() -> String {
+    String::from("x-tenant-id")
+}
+
+fn default_jwt_claim() -> String {
+    String::from("tenant")
+}
+
+pub fn default_tenant_extractors() -> Vec<TenantExtractor> {
+    alloc::vec![TenantExtractor::Host]
+}
+
+/// The parts of a request tenant extractors look at.
+pub struct TenantRequest<'a> {
+    /// `Host` header or `:authority`.
+    pub host: &'a str,
+    /// Request path, optionally with a query string.
+    pub path: &'a str,
+    /// Looks up a request header by lowercase name.
+    pub headers: &'a dyn Fn(&str) -> Option<String>,
+    /// Tenant the host is aliased to, if the router looked it up.
+    pub alias: Option<String>,
+}
+
+impl TenantExtractor {
+    /// Parse a comma-separated list of `host`, `path[:prefix]`,
+    /// `header[:name]` and `jwt[:claim]` entries.
+    ///
+    /// # Example
+    /// ```
+    /// use tenant_routing_core::extractor::TenantExtractor;
+    /// let extractors = TenantExtractor::from_spec("header:x-tenant-id, path, host").unwrap();
+    /// assert_eq!(extractors.len(), 3);
+    /// assert_eq!(extractors[2], TenantExtractor::Host);
+    /// ```
+    pub fn from_spec(spec: &str) -> Result<Vec<Self>, String> {
+        spec.split(',')
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+            .map(|entry| {
+                let (kind, arg) = match entry.split_once(':') {
+                    Some((kind, arg)) => (kind.trim(), Some(arg.trim().to_string())),
+                    None => (entry, None),
+                };
+
+                match (kind, arg) {
+                    ("host", None) => Ok(TenantExtractor::Host),
+                    ("path", prefix) => Ok(TenantExtractor::PathPrefix {
+                        prefix: prefix.unwrap_or_else(default_path_prefix),
+                    }),
+                    ("header", name) => Ok(TenantExtractor::Header {
+                        name: name.unwrap_or_else(default_tenant_header).to_lowercase(),
+                    }),
+                    ("jwt", claim) => Ok(TenantExtractor::JwtClaim {
+                        claim: claim.unwrap_or_else(default_jwt_claim),
+                    }),
+                    _ => Err(format!(
+                        "invalid tenant extractor '{}', expected host, path[:prefix], header[:name] or jwt[:claim]",
+                        entry
+                    )),
+                }
+            })
+            .collect()
+    }
+
+    /// Tenant this extractor finds in `request`, as a canonical tenant ID.
+    /// The host extractor is handled by the config, which knows the base
+    /// domains.
+    pub(crate) fn extract(&self, request: &TenantRequest) -> Option<String> {
+        let tenant = match self {
+            TenantExtractor::Host => return None,
+            TenantExtractor::PathPrefix { prefix } => tenant_from_path(request.path, prefix)?,
+            TenantExtractor::Header { name } => (request.headers)(name)?,
+            TenantExtractor::JwtClaim { claim } => {
+                tenant_from_jwt(&(request.headers)("authorization")?, claim)?
+            }
+        };
+
+        canonical_tenant_id(&tenant)
+    }
+
+    pub(crate) fn collect_errors(&self, path: &str, errors: &mut ConfigError) {
+        match self {
+            TenantExtractor::Host => {}
+            TenantExtractor::PathPrefix { prefix } => {
+                if !(prefix.starts_with('/') && prefix.ends_with('/')) {
+                    errors.push(
+                        format!("{}.prefix", path),
+                        ValidationError::InvalidPathPrefix(prefix.clone()),
+                    );
+                }
+            }
+            TenantExtractor::Header { name } => {
+                if name.is_empty() {
+                    errors.push(format!("{}.name", path), ValidationError::Empty);
+                }
+            }
+            TenantExtractor::JwtClaim { claim } => {
+                if claim.is_empty() {
+                    errors.push(format!("{}.claim", path), ValidationError::Empty);
+                }
+            }
+        }
+    }
+}
+
+/// # Example
+/// ```
+/// use tenant_routing_core::extractor::tenant_from_path;
+/// assert_eq!(tenant_from_path("/t/acme/orders?page=2", "/t/").as_deref(), Some("acme"));
+/// assert_eq!(tenant_from_path("/t/acme", "/t/").as_deref(), Some("acme"));
+/// assert_eq!(tenant_from_path("/orders", "/t/"), None);
+/// ```
+pub fn tenant_from_path(path: &str, prefix: &str) -> Option<String> {
+    let path = path.split(['?', '#']).next().unwrap_or(path);
+    let rest = path.strip_prefix(prefix)?;
+    let tenant = rest.split('/').next().unwrap_or(rest);
+
+    if tenant.is_empty() {
+        None
+    } else {
+        Some(tenant.to_string())
+    }
+}
+
+/// Read a string claim from a bearer token's payload without verifying it.
+///
+/// # Example
+/// ```
+/// use tenant_routing_core::extractor::tenant_from_jwt;
+/// // Payload: {"tenant":"acme"}
+/// let header = "Bearer eyJhbGciOiJub25lIn0.eyJ0ZW5hbnQiOiJhY21lIn0.";
+/// assert_eq!(tenant_from_jwt(header, "tenant").as_deref(), Some("acme"));
+/// assert_eq!(tenant_from_jwt(header, "org"), None);
+/// ```
+pub fn tenant_from_jwt(authorization: &str, claim: &str) -> Option<String> {
+    let authorization = authorization.trim();
+    let scheme = authorization.get(..7)?;
+    if !scheme.eq_ignore_ascii_case("bearer ") {
+        return None;
+    }
+
+    let payload = authorization[7..].trim().split('.').nth(1)?;
+    let payload = decode_base64url(payload)?;
+    let claims: serde_json::Value = serde_json::from_slice(&payload).ok()?;
+
+    claims.get(claim)?.as_str().map(|s| s.to_string())
+}
+
+/// Decode unpadded (or padded) base64url, as used by JWTs.
+fn decode_base64url(input: &str) -> Option<Vec<u8>> {
+    let input = input.trim_end_matches('=');
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for byte in input.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return None,
+        };
+
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Some(output)
+}
@@ -0,0 +1,126 @@
+//! Deterministic hashing shared by every router.
+//!
+//! Routing decisions derived from these hashes must agree between the WASM
+//! filter and the lookup service, and across releases, so the algorithm must
+//! never change. `core::hash::Hasher` implementations give no such guarantee.
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Hash `parts` as if they were joined with a separator that cannot appear
+/// in UTF-8 text, so `["ab", "c"]` and `["a", "bc"]` hash differently.
+///
+/// # Example
+/// ```
+/// use tenant_routing_core::hash::stable_hash;
+/// assert_eq!(stable_hash(&["tenant1", "user42"]), stable_hash(&["tenant1", "user42"]));
+/// assert_ne!(stable_hash(&["ab", "c"]), stable_hash(&["a", "bc"]));
+/// ```
+pub fn stable_hash(parts: &[&str]) -> u64 {
+    let mut hash = FNV_OFFSET_BASIS;
+
+    for (i, part) in parts.iter().enumerate() {
+        if i > 0 {
+            hash = fnv1a_byte(hash, 0xff);
+        }
+        for byte in part.bytes() {
+            hash = fnv1a_byte(hash, byte);
+        }
+    }
+
+    finalize(hash)
+}
+
+/// Pick one of `candidates` for `key` using rendezvous (highest random
+/// weight) hashing.
+///
+/// Each candidate is scored by hashing it together with the key and the
+/// highest score wins. Adding a candidate only moves the keys it now wins;
+/// every other key keeps its previous pick. The result does not depend on
+/// the order of `candidates`.
+///
+/// # Example
+/// ```
+/// use tenant_routing_core::hash::rendezvous_pick;
+/// let shards = ["shard1", "shard2", "shard3"];
+/// let pick = rendezvous_pick("acme", &shards).unwrap();
+/// assert!(shards.contains(&pick));
+/// assert_eq!(rendezvous_pick::<&str>("acme", &[]), None);
+/// ```
+pub fn rendezvous_pick<'a, S: AsRef<str>>(key: &str, candidates: &'a [S]) -> Option<&'a str> {
+    weighted_rendezvous_pick(key, candidates.iter().map(|c| (c.as_ref(), 1)))
+}
+
+/// Rendezvous hashing where each candidate carries a weight.
+///
+/// Each candidate is scored `-weight / ln(u)`, with `u` in (0, 1) derived
+/// from a single hash of the key and the candidate, so it wins in proportion
+/// to its weight at the same cost whatever the weight. Weight 0 never wins.
+///
+/// # Example
+/// ```
+/// use tenant_routing_core::hash::weighted_rendezvous_pick;
+/// let shards = [("shard1", 1), ("shard2", u32::MAX), ("shard3", 0)];
+/// assert!(weighted_rendezvous_pick("acme", shards).is_some());
+/// assert_eq!(weighted_rendezvous_pick("acme", [("shard3", 0)]), None);
+/// ```
+pub fn weighted_rendezvous_pick<'a>(
+    key: &str,
+    candidates: impl IntoIterator<Item = (&'a str, u32)>,
+) -> Option<&'a str> {
+    candidates
+        .into_iter()
+        .filter(|&(_, weight)| weight > 0)
+        .map(|(c, weight)| (weighted_score(key, c, weight), c))
+        // Break (unlikely) score ties by name so input order never matters
+        .max_by(|a, b| a.0.total_cmp(&b.0).then_with(|| b.1.cmp(a.1)))
+        .map(|(_, c)| c)
+}
+
+fn weighted_score(key: &str, candidate: &str, weight: u32) -> f64 {
+    // Top 53 bits, offset by half a step so u is never 0 or 1
+    let u = ((stable_hash(&[key, candidate]) >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+    -(weight as f64) / ln(u)
+}
+
+/// Natural logarithm of `x > 0`.
+///
+/// `core` has no `ln` without `std`, and platform implementations may
+/// differ in the last bit; this one only uses basic IEEE operations, so
+/// every router computes identical scores.
+fn ln(x: f64) -> f64 {
+    const LN_2: f64 = core::f64::consts::LN_2;
+
+    // x = m * 2^e with m in [1, 2)
+    let bits = x.to_bits();
+    let exponent = ((bits >> 52) & 0x7ff) as i64 - 1023;
+    let m = f64::from_bits((bits & 0x000f_ffff_ffff_ffff) | 0x3ff0_0000_0000_0000);
+
+    // ln(m) = 2 atanh(z) = 2 (z + z^3/3 + z^5/5 + ...), with z <= 1/3
+    let z = (m - 1.0) / (m + 1.0);
+    let z2 = z * z;
+    let mut term = z;
+    let mut sum = 0.0;
+    let mut n = 1.0;
+    while n < 60.0 {
+        sum += term / n;
+        term *= z2;
+        n += 2.0;
+    }
+
+    exponent as f64 * LN_2 + 2.0 * sum
+}
+
+fn fnv1a_byte(hash: u64, byte: u8) -> u64 {
+    (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+}
+
+/// SplitMix64 finalizer. FNV-1a alone distributes short, similar keys
+/// poorly in the low bits, which is what modulo arithmetic looks at.
+fn finalize(mut hash: u64) -> u64 {
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
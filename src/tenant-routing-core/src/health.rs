@@ -0,0 +1,125 @@
+//! Readiness reports served by the services' `/readyz` endpoints.
+//!
+//! A service is ready once every component it depends on (the mapping store,
+//! a warm cache, ...) is; the report lists each component so a failing probe
+//! says which one is holding it back.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::source::MappingSource;
+
+/// How long a readiness probe waits for the mapping store to answer.
+pub const READINESS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Canary object read to check the mapping store. Any answer, including
+/// "not found", shows the store is reachable and the credentials work.
+pub use crate::cache::GENERATION_OBJECT as CANARY_OBJECT;
+
+/// Component the mapping store is reported as.
+pub const MAPPING_STORE: &str = "mapping_store";
+
+/// Probe `source`, giving up after [`READINESS_TIMEOUT`].
+pub async fn probe_source(source: &dyn MappingSource) -> ComponentStatus {
+    let started = Instant::now();
+    let status = match tokio::time::timeout(READINESS_TIMEOUT, source.probe()).await {
+        Ok(Ok(())) => ComponentStatus::ready(),
+        Ok(Err(e)) => ComponentStatus::failing(e.to_string()),
+        Err(_) => ComponentStatus::failing(format!(
+            "no answer within {}s",
+            READINESS_TIMEOUT.as_secs()
+        )),
+    };
+    status.with_latency(started.elapsed())
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct ComponentStatus {
+    pub ready: bool,
+    /// Why the component is not ready
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// How long the component took to answer the check
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+}
+
+impl ComponentStatus {
+    pub fn ready() -> Self {
+        Self {
+            ready: true,
+            error: None,
+            latency_ms: None,
+        }
+    }
+
+    pub fn failing(error: impl Into<String>) -> Self {
+        Self {
+            ready: false,
+            error: Some(error.into()),
+            latency_ms: None,
+        }
+    }
+
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency_ms = Some(latency.as_millis().try_into().unwrap_or(u64::MAX));
+        self
+    }
+}
+
+/// Status of each component a service depends on.
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct ReadinessReport {
+    /// Whether every component is ready
+    pub ready: bool,
+    pub components: BTreeMap<String, ComponentStatus>,
+}
+
+impl Default for ReadinessReport {
+    fn default() -> Self {
+        Self {
+            ready: true,
+            components: BTreeMap::new(),
+        }
+    }
+}
+
+impl ReadinessReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A report on the mapping store, which every service depends on.
+    /// Services add their own components to it.
+    pub async fn for_source(source: &dyn MappingSource) -> Self {
+        let mut report = Self::new();
+        report.add(MAPPING_STORE, probe_source(source).await);
+        report
+    }
+
+    /// Components that are not ready, and why.
+    pub fn failures(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.components.iter().filter(|(_, status)| !status.ready).map(|(component, status)| {
+            (component.as_str(), status.error.as_deref().unwrap_or("not ready"))
+        })
+    }
+
+    /// Record `component`'s status; the report is only ready while every
+    /// component is.
+    ///
+    /// # Example
+    /// ```
+    /// use tenant_routing_core::health::{ComponentStatus, ReadinessReport};
+    /// let mut report = ReadinessReport::new();
+    /// report.add("mapping_store", ComponentStatus::ready());
+    /// assert!(report.ready);
+    /// report.add("cache", ComponentStatus::failing("warming up"));
+    /// assert!(!report.ready);
+    /// ```
+    pub fn add(&mut self, component: impl Into<String>, status: ComponentStatus) {
+        self.ready &= status.ready;
+        self.components.insert(component.into(), status);
+    }
+}
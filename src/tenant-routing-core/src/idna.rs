@@ -0,0 +1,67 @@
+//! Internationalized domain names.
+//!
+//! Browsers send non-ASCII hostnames as punycode (`xn--` labels), but people
+//! type and onboard tenants by their Unicode names. Tenant IDs therefore use
+//! the ASCII form everywhere (cache keys, object names, headers), and
+//! [`to_unicode`] recovers the display form.
+//!
+//! Conversion follows UTS #46 via the `idna` crate, so labels are case
+//! folded and NFC-normalized and every spelling of a name maps to one ID.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Prefix marking a punycode-encoded label.
+pub const ACE_PREFIX: &str = "xn--";
+
+/// Convert a domain or tenant ID to its lowercase ASCII form, encoding
+/// non-ASCII labels as punycode.
+///
+/// Returns `None` if a label is empty or the name is not a valid IDN, such
+/// as an `xn--` label that is not valid punycode.
+///
+/// # Example
+/// ```
+/// use tenant_routing_core::idna::to_ascii;
+/// assert_eq!(to_ascii("München.example.com").as_deref(), Some("xn--mnchen-3ya.example.com"));
+/// assert_eq!(to_ascii("Mu\u{308}nchen").as_deref(), Some("xn--mnchen-3ya"));
+/// assert_eq!(to_ascii("XN--MNCHEN-3YA").as_deref(), Some("xn--mnchen-3ya"));
+/// ```
+pub fn to_ascii(domain: &str) -> Option<String> {
+    // UTS #46 lets empty labels through; a tenant ID never has one
+    if domain.split('.').any(str::is_empty) {
+        return None;
+    }
+
+    ::idna::domain_to_ascii(domain).ok()
+}
+
+/// Convert a domain or tenant ID to its Unicode form for display. Labels
+/// that are not valid punycode are returned lowercased.
+///
+/// # Example
+/// ```
+/// use tenant_routing_core::idna::to_unicode;
+/// assert_eq!(to_unicode("xn--mnchen-3ya.example.com"), "münchen.example.com");
+/// ```
+pub fn to_unicode(domain: &str) -> String {
+    let labels: Vec<String> = domain
+        .split('.')
+        .map(|label| label_to_unicode(label).unwrap_or_else(|| label.to_lowercase()))
+        .collect();
+
+    labels.join(".")
+}
+
+/// Unicode form of a single label, or `None` if it is not a valid IDN label.
+pub(crate) fn label_to_unicode(label: &str) -> Option<String> {
+    let (unicode, result) = ::idna::domain_to_unicode(label);
+    result.ok().map(|_| unicode)
+}
+
+pub(crate) fn strip_ace_prefix(label: &str) -> Option<&str> {
+    label
+        .get(..ACE_PREFIX.len())
+        .filter(|prefix| prefix.eq_ignore_ascii_case(ACE_PREFIX))
+        .map(|_| &label[ACE_PREFIX.len()..])
+}
@@ -2,9 +2,26 @@
 
 extern crate alloc;
 
+pub mod alias;
 pub mod config;
+pub mod error;
+pub mod extractor;
 pub mod tenant;
 pub mod cache;
+pub mod hash;
+pub mod idna;
+pub mod loader;
+pub mod mapping;
+pub mod migration;
+pub mod registry;
+
+#[cfg(any(test, feature = "test-vectors"))]
+pub mod test_vectors;
+
+#[cfg(feature = "std")]
+pub mod health;
+#[cfg(feature = "std")]
+pub mod source;
 
 #[cfg(test)]
 mod tests;
\ No newline at end of file
@@ -0,0 +1,229 @@
+//! Loading [`TenantRoutingConfig`] from layered sources.
+//!
+//! Layers are applied in order, each overriding the top-level keys it sets:
+//! the built-in defaults, then a TOML or YAML file, then environment
+//! variables, then (in the WASM filter) the plugin configuration JSON. Each
+//! layer is checked against the schema on its own, so an unknown key or a
+//! value of the wrong type is reported against the file or variable it came
+//! from.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::config::TenantRoutingConfig;
+use crate::error::LoadError;
+use crate::extractor::TenantExtractor;
+use crate::registry::ShardRegistry;
+use crate::tenant::BaseDomain;
+
+/// Environment variable naming the configuration file the services load.
+pub const CONFIG_FILE_ENV: &str = "CONFIG_FILE";
+
+/// Environment variables and the configuration keys they override.
+pub const ENV_VARS: &[(&str, &str)] = &[
+    ("GCS_BUCKET", "gcs_bucket"),
+    ("CACHE_TTL", "cache_ttl_seconds"),
+    ("CACHE_TTL_JITTER_PERCENT", "cache_ttl_jitter_percent"),
+    ("REFRESH_AHEAD_PERCENT", "refresh_ahead_percent"),
+    ("STALE_WHILE_REVALIDATE", "stale_while_revalidate_seconds"),
+    ("MAX_STALE", "max_stale_seconds"),
+    ("NEGATIVE_CACHE_TTL", "negative_cache_ttl_seconds"),
+    ("BACKEND_ERROR_POLICY", "backend_error_policy"),
+    ("GENERATION_CHECK_INTERVAL", "generation_check_seconds"),
+    ("DEFAULT_SHARD", "default_shard"),
+    ("FALLBACK_SHARDS", "fallback_shards"),
+    ("SHARDS", "shards"),
+    ("SPLIT_KEY_HEADER", "split_key_header"),
+    ("BASE_DOMAINS", "base_domains"),
+    ("TENANT_EXTRACTORS", "tenant_extractors"),
+    ("PORT", "port"),
+    ("PREWARM", "prewarm"),
+    ("PREWARM_TENANTS", "prewarm_tenants"),
+    ("PREWARM_CONCURRENCY", "prewarm_concurrency"),
+];
+
+/// Builds a configuration from layers, later layers winning.
+///
+/// # Example
+/// ```
+/// use tenant_routing_core::loader::ConfigLoader;
+/// let config = ConfigLoader::new()
+///     .env(|name| (name == "CACHE_TTL").then(|| "60".to_string()))
+///     .unwrap()
+///     .json("plugin configuration", r#"{"default_shard": "shard2"}"#)
+///     .unwrap()
+///     .load()
+///     .unwrap();
+/// assert_eq!(config.cache_ttl_seconds, 60);
+/// assert_eq!(config.default_shard, "shard2");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ConfigLoader {
+    values: Map<String, Value>,
+}
+
+impl ConfigLoader {
+    /// A loader holding only the built-in defaults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply a JSON object, e.g. the WASM plugin configuration. `origin`
+    /// names it in errors.
+    pub fn json(self, origin: &str, raw: &str) -> Result<Self, LoadError> {
+        let layer = serde_json::from_str(raw).map_err(|e| layer_error(origin, e))?;
+        self.apply(origin, layer)
+    }
+
+    /// Apply a TOML document.
+    #[cfg(feature = "file")]
+    pub fn toml(self, origin: &str, raw: &str) -> Result<Self, LoadError> {
+        let layer = toml::from_str(raw).map_err(|e| layer_error(origin, e))?;
+        self.apply(origin, layer)
+    }
+
+    /// Apply a YAML document.
+    #[cfg(feature = "file")]
+    pub fn yaml(self, origin: &str, raw: &str) -> Result<Self, LoadError> {
+        let layer = serde_yaml::from_str(raw).map_err(|e| layer_error(origin, e))?;
+        self.apply(origin, layer)
+    }
+
+    /// Apply a `.toml`, `.yaml` or `.yml` file, picking the format by
+    /// extension.
+    #[cfg(feature = "file")]
+    pub fn file(self, path: impl AsRef<std::path::Path>) -> Result<Self, LoadError> {
+        let path = path.as_ref();
+        let origin = path.display().to_string();
+        let raw = std::fs::read_to_string(path).map_err(|e| layer_error(&origin, e))?;
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => self.toml(&origin, &raw),
+            Some("yaml" | "yml") => self.yaml(&origin, &raw),
+            _ => Err(layer_error(
+                &origin,
+                "unsupported configuration format, expected .toml, .yaml or .yml",
+            )),
+        }
+    }
+
+    /// Apply the variables in [`ENV_VARS`] that `lookup` finds. Lists use the
+    /// same specs as the `from_spec` parsers, e.g. `SHARDS=shard1,shard2:2`.
+    pub fn env(mut self, lookup: impl Fn(&str) -> Option<String>) -> Result<Self, LoadError> {
+        for (name, key) in ENV_VARS {
+            let Some(raw) = lookup(name) else {
+                continue;
+            };
+
+            let value = env_value(key, &raw).map_err(|e| layer_error(name, e))?;
+            let mut layer = Map::new();
+            layer.insert(key.to_string(), value);
+            self = self.apply(name, Value::Object(layer))?;
+        }
+
+        Ok(self)
+    }
+
+    /// Apply the process environment.
+    #[cfg(feature = "std")]
+    pub fn process_env(self) -> Result<Self, LoadError> {
+        self.env(|name| std::env::var(name).ok())
+    }
+
+    /// Merge the layers and validate the result.
+    pub fn load(self) -> Result<TenantRoutingConfig, LoadError> {
+        let config = TenantRoutingConfig::deserialize(&Value::Object(self.values))
+            .map_err(|e| layer_error("configuration", e))?;
+        config.validate().map_err(LoadError::Invalid)?;
+        Ok(config)
+    }
+
+    fn apply(mut self, origin: &str, layer: Value) -> Result<Self, LoadError> {
+        let Value::Object(layer) = layer else {
+            return Err(layer_error(origin, "expected a table of settings"));
+        };
+
+        // Check the layer alone so errors name where the bad key came from
+        let layer = Value::Object(layer);
+        TenantRoutingConfig::deserialize(&layer).map_err(|e| layer_error(origin, e))?;
+
+        if let Value::Object(layer) = layer {
+            self.values.extend(layer);
+        }
+        Ok(self)
+    }
+}
+
+/// The services' configuration: defaults, then the file named by
+/// [`CONFIG_FILE_ENV`] if set, then the process environment.
+#[cfg(feature = "file")]
+pub fn load_service_config() -> Result<TenantRoutingConfig, LoadError> {
+    let mut loader = ConfigLoader::new();
+    if let Ok(path) = std::env::var(CONFIG_FILE_ENV) {
+        loader = loader.file(path)?;
+    }
+    loader.process_env()?.load()
+}
+
+/// The problems in a failed load, one per log line: each invalid field on
+/// its own, or the layer that could not be read.
+///
+/// # Example
+/// ```
+/// use tenant_routing_core::loader::{load_error_messages, ConfigLoader};
+/// let error = ConfigLoader::new()
+///     .json("plugin configuration", r#"{"default_shard": ""}"#)
+///     .and_then(|loader| loader.load())
+///     .unwrap_err();
+/// assert_eq!(load_error_messages(&error), ["default_shard cannot be empty"]);
+/// ```
+pub fn load_error_messages(error: &LoadError) -> Vec<String> {
+    match error {
+        LoadError::Invalid(errors) => errors.errors().iter().map(ToString::to_string).collect(),
+        LoadError::Layer { .. } => alloc::vec![error.to_string()],
+    }
+}
+
+fn env_value(key: &str, raw: &str) -> Result<Value, String> {
+    let raw = raw.trim();
+    let value = match key {
+        "cache_ttl_seconds"
+        | "cache_ttl_jitter_percent"
+        | "refresh_ahead_percent"
+        | "stale_while_revalidate_seconds"
+        | "max_stale_seconds"
+        | "negative_cache_ttl_seconds"
+        | "generation_check_seconds"
+        | "prewarm_concurrency"
+        | "port" => raw
+            .parse::<u64>()
+            .map(Value::from)
+            .map_err(|_| format!("must be a number, got '{}'", raw))?,
+        "fallback_shards" | "prewarm_tenants" => raw
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| Value::String(s.to_string()))
+            .collect(),
+        "shards" => to_value(ShardRegistry::from_spec(raw)?)?,
+        "base_domains" => to_value(BaseDomain::from_spec(raw)?)?,
+        "tenant_extractors" => to_value(TenantExtractor::from_spec(raw)?)?,
+        _ => Value::String(raw.to_string()),
+    };
+
+    Ok(value)
+}
+
+fn to_value(value: impl serde::Serialize) -> Result<Value, String> {
+    serde_json::to_value(value).map_err(|e| e.to_string())
+}
+
+fn layer_error(origin: &str, message: impl ToString) -> LoadError {
+    LoadError::Layer {
+        origin: origin.to_string(),
+        message: message.to_string(),
+    }
+}
@@ -0,0 +1,271 @@
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::hash::stable_hash;
+use crate::migration::{MigrationState, RequestContext, RouteDecision};
+use crate::tenant::normalize_shard_name;
+
+/// Highest mapping record schema version this crate understands.
+pub const MAPPING_SCHEMA_VERSION: u32 = 1;
+
+/// Contents of a `<tenant>/shard` object.
+///
+/// Records are stored as JSON, e.g.
+/// `{"schema_version":1,"shard":"shard2","metadata":{"tier":"gold"}}`.
+/// Objects that only contain a bare shard name (the original format) are
+/// still accepted by [`parse_mapping_record`].
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct MappingRecord {
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
+    pub shard: String,
+    /// Optional weighted split, e.g. 90% on the current shard and 10% on
+    /// the shard a tenant is moving to. When empty, all traffic uses `shard`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub splits: Vec<WeightedShard>,
+    /// Set while the tenant is being moved between shards. Takes precedence
+    /// over `shard` and `splits`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub migration: Option<MigrationState>,
+    #[serde(default, skip_serializing_if = "MappingMetadata::is_empty")]
+    pub metadata: MappingMetadata,
+    /// Unix timestamp (seconds) of the last update, if the writer recorded one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WeightedShard {
+    pub shard: String,
+    pub weight: u32,
+}
+
+impl WeightedShard {
+    pub fn new(shard: String, weight: u32) -> Self {
+        Self { shard, weight }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct MappingMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tier: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl MappingMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.tier.is_none() && self.region.is_none() && self.labels.is_empty()
+    }
+}
+
+fn default_schema_version() -> u32 {
+    MAPPING_SCHEMA_VERSION
+}
+
+impl MappingRecord {
+    pub fn new(shard: String) -> Self {
+        Self {
+            schema_version: MAPPING_SCHEMA_VERSION,
+            shard,
+            splits: Vec::new(),
+            migration: None,
+            metadata: MappingMetadata::default(),
+            updated_at: None,
+        }
+    }
+
+    /// Pick the shard for a request. See [`select_weighted_shard`].
+    pub fn select_shard(&self, tenant: &str, key: &str) -> &str {
+        select_weighted_shard(&self.shard, &self.splits, tenant, key)
+    }
+
+    /// Route a request, taking any migration in progress into account.
+    pub fn route(&self, ctx: &RequestContext) -> RouteDecision<'_> {
+        route_mapping(&self.shard, &self.splits, self.migration.as_ref(), ctx)
+    }
+}
+
+/// Route a request for a mapping given as its parts, so cached copies of a
+/// record route exactly like the record itself.
+pub fn route_mapping<'a>(
+    shard: &'a str,
+    splits: &'a [WeightedShard],
+    migration: Option<&'a MigrationState>,
+    ctx: &RequestContext,
+) -> RouteDecision<'a> {
+    match migration {
+        Some(migration) => migration.route(ctx.now, ctx.is_write),
+        None => RouteDecision::Forward {
+            shard: select_weighted_shard(shard, splits, ctx.tenant, ctx.key),
+            phase: None,
+        },
+    }
+}
+
+/// Deterministically pick a shard from a weighted split.
+///
+/// `key` should identify the user or session (a session ID, client IP, ...)
+/// so that the same user keeps landing on the same shard. The tenant is part
+/// of the hash so a user's bucket in one tenant says nothing about another.
+/// Falls back to `primary` when `splits` is empty or carries no weight.
+///
+/// # Example
+/// ```
+/// use tenant_routing_core::mapping::{select_weighted_shard, WeightedShard};
+/// let splits = vec![WeightedShard::new("shard1".into(), 0), WeightedShard::new("shard2".into(), 100)];
+/// assert_eq!(select_weighted_shard("shard1", &splits, "acme", "session-1"), "shard2");
+/// assert_eq!(select_weighted_shard("shard1", &[], "acme", "session-1"), "shard1");
+/// ```
+pub fn select_weighted_shard<'a>(
+    primary: &'a str,
+    splits: &'a [WeightedShard],
+    tenant: &str,
+    key: &str,
+) -> &'a str {
+    let total: u64 = splits.iter().map(|s| s.weight as u64).sum();
+    if total == 0 {
+        return primary;
+    }
+
+    let mut point = stable_hash(&[tenant, key]) % total;
+    for split in splits {
+        if point < split.weight as u64 {
+            return &split.shard;
+        }
+        point -= split.weight as u64;
+    }
+
+    primary
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MappingError {
+    /// The record does not name a shard.
+    EmptyShard,
+    /// The record looks like JSON but could not be decoded.
+    InvalidJson(String),
+    /// The record was written with a newer schema than this crate supports.
+    UnsupportedVersion(u32),
+    /// The weighted split is present but all weights are zero.
+    ZeroSplitWeight,
+    /// The record routes to a shard missing from the shard registry.
+    UnknownShard(String),
+    /// An alias record does not name a valid tenant.
+    InvalidTenant(String),
+    /// The cache generation object does not hold a number.
+    InvalidGeneration(String),
+}
+
+impl fmt::Display for MappingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MappingError::EmptyShard => write!(f, "mapping record has an empty shard"),
+            MappingError::InvalidJson(e) => write!(f, "invalid mapping record JSON: {}", e),
+            MappingError::UnsupportedVersion(v) => write!(
+                f,
+                "unsupported mapping schema version {} (max {})",
+                v, MAPPING_SCHEMA_VERSION
+            ),
+            MappingError::ZeroSplitWeight => {
+                write!(f, "mapping record split has no positive weight")
+            }
+            MappingError::UnknownShard(shard) => {
+                write!(f, "mapping record routes to unknown shard '{}'", shard)
+            }
+            MappingError::InvalidTenant(tenant) => {
+                write!(f, "alias record names invalid tenant '{}'", tenant)
+            }
+            MappingError::InvalidGeneration(generation) => {
+                write!(f, "cache generation '{}' is not a number", generation)
+            }
+        }
+    }
+}
+
+/// How a tenant's mapping lookup ended, as reported by the routers.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LookupOutcome {
+    /// A valid mapping was found.
+    Found,
+    /// The tenant has no mapping object.
+    NotFound,
+    /// The mapping object exists but was rejected, see [`MappingError`].
+    InvalidRecord,
+    /// The mapping store failed, so whether a mapping exists is unknown.
+    BackendError,
+}
+
+impl LookupOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LookupOutcome::Found => "found",
+            LookupOutcome::NotFound => "not_found",
+            LookupOutcome::InvalidRecord => "invalid_record",
+            LookupOutcome::BackendError => "backend_error",
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MappingError {}
+
+/// Parse the raw contents of a `<tenant>/shard` object.
+///
+/// # Examples
+/// ```
+/// use tenant_routing_core::mapping::parse_mapping_record;
+/// assert_eq!(parse_mapping_record("SHARD2\n").unwrap().shard, "shard2");
+/// assert_eq!(parse_mapping_record(r#"{"schema_version":1,"shard":"shard3"}"#).unwrap().shard, "shard3");
+/// ```
+pub fn parse_mapping_record(raw: &str) -> Result<MappingRecord, MappingError> {
+    let raw = raw.trim();
+
+    let mut record = if raw.starts_with('{') {
+        let record: MappingRecord =
+            serde_json::from_str(raw).map_err(|e| MappingError::InvalidJson(e.to_string()))?;
+
+        if record.schema_version > MAPPING_SCHEMA_VERSION {
+            return Err(MappingError::UnsupportedVersion(record.schema_version));
+        }
+
+        record
+    } else {
+        // Legacy format: the object is just the shard name
+        MappingRecord::new(raw.to_string())
+    };
+
+    record.shard = normalize_shard_name(&record.shard);
+
+    if record.shard.is_empty() {
+        return Err(MappingError::EmptyShard);
+    }
+
+    for split in record.splits.iter_mut() {
+        split.shard = normalize_shard_name(&split.shard);
+        if split.shard.is_empty() {
+            return Err(MappingError::EmptyShard);
+        }
+    }
+
+    if !record.splits.is_empty() && record.splits.iter().all(|s| s.weight == 0) {
+        return Err(MappingError::ZeroSplitWeight);
+    }
+
+    if let Some(migration) = record.migration.as_mut() {
+        migration.source_shard = normalize_shard_name(&migration.source_shard);
+        migration.target_shard = normalize_shard_name(&migration.target_shard);
+        if migration.source_shard.is_empty() || migration.target_shard.is_empty() {
+            return Err(MappingError::EmptyShard);
+        }
+    }
+
+    Ok(record)
+}
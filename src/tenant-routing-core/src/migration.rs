@@ -0,0 +1,144 @@
+use alloc::string::String;
+use serde::{Deserialize, Serialize};
+
+/// Header set on forwarded requests (and 503 replies) while a tenant has a
+/// migration in progress.
+pub const MIGRATION_PHASE_HEADER: &str = "x-tenant-migration-phase";
+
+/// Phases of moving a tenant from one shard to another, in order.
+///
+/// - `Active`: the migration is planned; all traffic goes to the source.
+/// - `Draining`: all traffic still goes to the source, but backends are told
+///   (via the phase header) to finish background work for the tenant.
+/// - `ReadOnly`: the tenant is frozen. Reads go to the source, writes are
+///   rejected with 503 and `Retry-After` while data is copied.
+/// - `Moved`: cutover is done; all traffic goes to the target.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationPhase {
+    Active,
+    Draining,
+    ReadOnly,
+    Moved,
+}
+
+impl MigrationPhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MigrationPhase::Active => "active",
+            MigrationPhase::Draining => "draining",
+            MigrationPhase::ReadOnly => "read_only",
+            MigrationPhase::Moved => "moved",
+        }
+    }
+
+    /// The phase a tenant is in before this one takes effect.
+    pub fn previous(&self) -> MigrationPhase {
+        match self {
+            MigrationPhase::Active | MigrationPhase::Draining => MigrationPhase::Active,
+            MigrationPhase::ReadOnly => MigrationPhase::Draining,
+            MigrationPhase::Moved => MigrationPhase::ReadOnly,
+        }
+    }
+}
+
+fn default_retry_after_seconds() -> u64 {
+    30
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MigrationState {
+    pub source_shard: String,
+    pub target_shard: String,
+    pub phase: MigrationPhase,
+    /// Unix timestamp (seconds) at which `phase` takes effect. Until then the
+    /// previous phase applies, so a record can be published ahead of time and
+    /// every router flips at the same moment regardless of cache age.
+    #[serde(default)]
+    pub effective_at: u64,
+    /// `Retry-After` value sent with 503 replies while the tenant is frozen.
+    #[serde(default = "default_retry_after_seconds")]
+    pub retry_after_seconds: u64,
+}
+
+impl MigrationState {
+    pub fn new(source_shard: String, target_shard: String, phase: MigrationPhase) -> Self {
+        Self {
+            source_shard,
+            target_shard,
+            phase,
+            effective_at: 0,
+            retry_after_seconds: default_retry_after_seconds(),
+        }
+    }
+
+    /// Phase in effect at `now`.
+    pub fn current_phase(&self, now: u64) -> MigrationPhase {
+        if now >= self.effective_at {
+            self.phase
+        } else {
+            self.phase.previous()
+        }
+    }
+
+    /// Decide where a request goes while the migration is in progress.
+    pub fn route(&self, now: u64, is_write: bool) -> RouteDecision<'_> {
+        let phase = self.current_phase(now);
+
+        match phase {
+            MigrationPhase::ReadOnly if is_write => RouteDecision::Unavailable {
+                phase,
+                retry_after_seconds: self.retry_after_seconds,
+            },
+            MigrationPhase::Moved => RouteDecision::Forward {
+                shard: &self.target_shard,
+                phase: Some(phase),
+            },
+            _ => RouteDecision::Forward {
+                shard: &self.source_shard,
+                phase: Some(phase),
+            },
+        }
+    }
+}
+
+/// Outcome of routing one request for a tenant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RouteDecision<'a> {
+    /// Send the request to `shard`, tagging it with `phase` if the tenant is
+    /// being migrated.
+    Forward {
+        shard: &'a str,
+        phase: Option<MigrationPhase>,
+    },
+    /// Reply 503 with `Retry-After` instead of forwarding.
+    Unavailable {
+        phase: MigrationPhase,
+        retry_after_seconds: u64,
+    },
+}
+
+/// Per-request inputs for routing decisions.
+#[derive(Clone, Copy, Debug)]
+pub struct RequestContext<'a> {
+    pub tenant: &'a str,
+    /// Session or client key; see `mapping::select_weighted_shard`.
+    pub key: &'a str,
+    pub is_write: bool,
+    /// Current Unix time in seconds.
+    pub now: u64,
+}
+
+/// Whether an HTTP method may modify tenant data.
+///
+/// # Example
+/// ```
+/// use tenant_routing_core::migration::is_write_method;
+/// assert!(is_write_method("POST"));
+/// assert!(!is_write_method("get"));
+/// ```
+pub fn is_write_method(method: &str) -> bool {
+    !["GET", "HEAD", "OPTIONS", "TRACE"]
+        .iter()
+        .any(|m| m.eq_ignore_ascii_case(method))
+}
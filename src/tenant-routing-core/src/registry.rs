@@ -0,0 +1,220 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::error::{ConfigError, ValidationError};
+use crate::mapping::{MappingError, MappingRecord};
+use crate::tenant::normalize_shard_name;
+
+fn default_capacity_weight() -> u32 {
+    1
+}
+
+/// Deserialize a shard name in the lowercase form mapping records use.
+pub(crate) fn deserialize_shard_name<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<String, D::Error> {
+    String::deserialize(deserializer).map(|name| normalize_shard_name(&name))
+}
+
+pub(crate) fn deserialize_shard_names<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    let names = Vec::<String>::deserialize(deserializer)?;
+    Ok(names.iter().map(|name| normalize_shard_name(name)).collect())
+}
+
+fn deserialize_failover<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    let failover = Option::<String>::deserialize(deserializer)?;
+    Ok(failover.map(|name| normalize_shard_name(&name)))
+}
+
+/// Largest accepted capacity weight; weights are relative, so this leaves
+/// plenty of room while catching typos like an extra few zeros.
+pub const MAX_CAPACITY_WEIGHT: u32 = 10_000;
+
+/// A shard that requests can be routed to, i.e. an Envoy cluster.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ShardInfo {
+    #[serde(deserialize_with = "deserialize_shard_name")]
+    pub name: String,
+    /// Shard that takes this shard's traffic while its capacity weight is 0.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_failover",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub failover: Option<String>,
+    /// Relative share of unmapped tenants placed on this shard by the hash
+    /// fallback. A weight of 0 takes the shard out of rotation.
+    #[serde(default = "default_capacity_weight")]
+    pub capacity_weight: u32,
+}
+
+impl ShardInfo {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            failover: None,
+            capacity_weight: default_capacity_weight(),
+        }
+    }
+}
+
+/// The set of shards mappings are allowed to point at.
+///
+/// Names are lowercased like mapping targets, however they were written
+/// in the configuration. An empty registry disables validation so existing
+/// deployments keep working until they configure one.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct ShardRegistry {
+    shards: Vec<ShardInfo>,
+}
+
+impl ShardRegistry {
+    pub fn new(shards: Vec<ShardInfo>) -> Self {
+        Self { shards }
+    }
+
+    /// Parse a `name[:weight[:failover]]` list, e.g. `shard1,shard2:2,shard3:0:shard1`.
+    ///
+    /// # Example
+    /// ```
+    /// use tenant_routing_core::registry::ShardRegistry;
+    /// let registry = ShardRegistry::from_spec("shard1, shard2:0:shard1").unwrap();
+    /// assert!(registry.contains("shard2"));
+    /// assert_eq!(registry.resolve("shard2"), Ok("shard1"));
+    /// ```
+    pub fn from_spec(spec: &str) -> Result<Self, String> {
+        let mut shards = Vec::new();
+
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let mut parts = entry.split(':').map(str::trim);
+            let name = normalize_shard_name(parts.next().unwrap_or_default());
+            let mut shard = ShardInfo::new(name);
+
+            if let Some(weight) = parts.next() {
+                shard.capacity_weight = weight
+                    .parse()
+                    .map_err(|_| format!("invalid capacity weight in shard entry '{}'", entry))?;
+            }
+            if let Some(failover) = parts.next() {
+                shard.failover = Some(normalize_shard_name(failover));
+            }
+            if shard.name.is_empty() || parts.next().is_some() {
+                return Err(format!(
+                    "invalid shard entry '{}', expected name[:weight[:failover]]",
+                    entry
+                ));
+            }
+
+            shards.push(shard);
+        }
+
+        Ok(Self::new(shards))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.is_empty()
+    }
+
+    pub fn shards(&self) -> &[ShardInfo] {
+        &self.shards
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ShardInfo> {
+        self.shards.iter().find(|s| s.name == name)
+    }
+
+    /// Whether `name` may be routed to. Always true for an empty registry,
+    /// which accepts every shard rather than none.
+    pub fn contains(&self, name: &str) -> bool {
+        self.is_empty() || self.get(name).is_some()
+    }
+
+    /// Capacity weight used by the hash fallback. Shards that are not
+    /// registered count as weight 1.
+    pub fn capacity_weight(&self, name: &str) -> u32 {
+        self.get(name)
+            .map(|s| s.capacity_weight)
+            .unwrap_or_else(default_capacity_weight)
+    }
+
+    /// The shard a request for `name` should actually be sent to: the shard
+    /// itself, or its failover while it is out of rotation.
+    pub fn resolve<'a>(&'a self, name: &'a str) -> Result<&'a str, MappingError> {
+        if self.is_empty() {
+            return Ok(name);
+        }
+
+        match self.get(name) {
+            Some(ShardInfo {
+                capacity_weight: 0,
+                failover: Some(failover),
+                ..
+            }) => Ok(failover),
+            Some(_) => Ok(name),
+            None => Err(MappingError::UnknownShard(name.to_string())),
+        }
+    }
+
+    /// Check that every shard a record can route to is registered. Any
+    /// record passes an empty registry.
+    pub fn validate_record(&self, record: &MappingRecord) -> Result<(), MappingError> {
+        let splits = record.splits.iter().map(|s| s.shard.as_str());
+        let migration = record
+            .migration
+            .iter()
+            .flat_map(|m| [m.source_shard.as_str(), m.target_shard.as_str()]);
+
+        match core::iter::once(record.shard.as_str())
+            .chain(splits)
+            .chain(migration)
+            .find(|shard| !self.contains(shard))
+        {
+            Some(unknown) => Err(MappingError::UnknownShard(unknown.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    /// Check that names are set and unique, that capacity weights are at most
+    /// [`MAX_CAPACITY_WEIGHT`] and that failover targets are themselves
+    /// registered.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut errors = ConfigError::default();
+        self.collect_errors("shards", &mut errors);
+        errors.into_result()
+    }
+
+    pub(crate) fn collect_errors(&self, path: &str, errors: &mut ConfigError) {
+        for (i, shard) in self.shards.iter().enumerate() {
+            if shard.name.is_empty() {
+                errors.push(format!("{}[{}].name", path, i), ValidationError::Empty);
+            } else if self.shards[..i].iter().any(|s| s.name == shard.name) {
+                errors.push(
+                    format!("{}[{}].name", path, i),
+                    ValidationError::DuplicateShard(shard.name.clone()),
+                );
+            }
+            if shard.capacity_weight > MAX_CAPACITY_WEIGHT {
+                errors.push(
+                    format!("{}[{}].capacity_weight", path, i),
+                    ValidationError::AboveMaximum(MAX_CAPACITY_WEIGHT.into()),
+                );
+            }
+            if let Some(failover) = &shard.failover
+                && (failover == &shard.name || self.get(failover).is_none())
+            {
+                errors.push(
+                    format!("{}[{}].failover", path, i),
+                    ValidationError::InvalidFailover(failover.clone()),
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,679 @@
+//! Backends that tenant mapping objects can be read from.
+//!
+//! Objects use the same names as in the GCS bucket (e.g. `tenant1/shard`),
+//! so a local directory or an in-memory map can stand in for the bucket
+//! when running on a laptop or in CI.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+
+use crate::alias::build_alias_object_name;
+use crate::cache::GENERATION_OBJECT;
+use crate::hash::stable_hash;
+use crate::health::CANARY_OBJECT;
+use crate::tenant::{build_gcs_object_name, parse_gcs_object_name};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SourceError {
+    /// The object does not exist in the backend.
+    NotFound(String),
+    /// The backend could not be reached or returned an unexpected error.
+    Backend(String),
+    /// A conditional write found the object changed, created or deleted
+    /// since the version it was made against.
+    PreconditionFailed(String),
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceError::NotFound(object) => write!(f, "object not found: {}", object),
+            SourceError::Backend(message) => write!(f, "backend error: {}", message),
+            SourceError::PreconditionFailed(object) => {
+                write!(f, "object was modified concurrently: {}", object)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+/// An object's contents and the version they were read at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionedObject {
+    pub contents: String,
+    /// Changes whenever the object is written, e.g. the GCS generation.
+    pub version: u64,
+}
+
+/// Condition a write or delete is made under, so concurrent edits are
+/// detected rather than overwritten.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precondition {
+    /// The object must not exist yet.
+    DoesNotExist,
+    /// The object must still be at this version.
+    Matches(u64),
+}
+
+impl Precondition {
+    /// Whether an object currently at `version` (`None` if missing)
+    /// satisfies the condition.
+    pub fn holds(&self, version: Option<u64>) -> bool {
+        match self {
+            Precondition::DoesNotExist => version.is_none(),
+            Precondition::Matches(expected) => version == Some(*expected),
+        }
+    }
+}
+
+fn read_only(source: &(impl MappingSource + ?Sized)) -> SourceError {
+    SourceError::Backend(format!("{} does not support writes", source.describe()))
+}
+
+#[async_trait]
+pub trait MappingSource: Send + Sync {
+    /// Human readable description used in logs, e.g. `gs://my-bucket`.
+    fn describe(&self) -> String;
+
+    /// Read the raw contents of `object`.
+    async fn read_object(&self, object: &str) -> Result<String, SourceError>;
+
+    /// Read the shard mapping object for `tenant`.
+    async fn read_tenant_mapping(&self, tenant: &str) -> Result<String, SourceError> {
+        self.read_object(&build_gcs_object_name(tenant)).await
+    }
+
+    /// Read the alias object for `host`, see [`crate::alias`].
+    async fn read_alias(&self, host: &str) -> Result<String, SourceError> {
+        self.read_object(&build_alias_object_name(host)).await
+    }
+
+    /// Read the cache generation object, see [`GENERATION_OBJECT`].
+    async fn read_generation(&self) -> Result<String, SourceError> {
+        self.read_object(GENERATION_OBJECT).await
+    }
+
+    /// Every tenant with a mapping object, in no particular order.
+    async fn list_tenants(&self) -> Result<Vec<String>, SourceError> {
+        Err(SourceError::Backend(format!(
+            "{} does not support listing",
+            self.describe()
+        )))
+    }
+
+    /// Check that the backend answers, by reading the canary object. A
+    /// missing canary still counts as an answer.
+    async fn probe(&self) -> Result<(), SourceError> {
+        match self.read_object(CANARY_OBJECT).await {
+            Ok(_) | Err(SourceError::NotFound(_)) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read `object` along with its version, for a later conditional write.
+    async fn read_versioned(&self, object: &str) -> Result<VersionedObject, SourceError> {
+        let _ = object;
+        Err(read_only(self))
+    }
+
+    /// Write `object` if `precondition` holds, returning its new version.
+    async fn write_object(
+        &self,
+        object: &str,
+        contents: &str,
+        precondition: Precondition,
+    ) -> Result<u64, SourceError> {
+        let _ = (object, contents, precondition);
+        Err(read_only(self))
+    }
+
+    /// Delete `object` if `precondition` holds.
+    async fn delete_object(&self, object: &str, precondition: Precondition) -> Result<(), SourceError> {
+        let _ = (object, precondition);
+        Err(read_only(self))
+    }
+}
+
+/// Reads objects from a directory laid out like the bucket, i.e.
+/// `<root>/<tenant>/shard`. An object's version is a hash of its contents,
+/// and conditional writes are only safe against a single writer.
+pub struct LocalDirSource {
+    root: PathBuf,
+}
+
+impl LocalDirSource {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn object_path(&self, object: &str) -> Option<PathBuf> {
+        let relative = Path::new(object);
+        // Object names come from request paths, so never let them escape the root
+        if relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            Some(self.root.join(relative))
+        } else {
+            None
+        }
+    }
+}
+
+#[async_trait]
+impl MappingSource for LocalDirSource {
+    fn describe(&self) -> String {
+        format!("file://{}", self.root.display())
+    }
+
+    async fn read_object(&self, object: &str) -> Result<String, SourceError> {
+        let path = self
+            .object_path(object)
+            .ok_or_else(|| SourceError::NotFound(object.to_string()))?;
+
+        tokio::fs::read_to_string(&path).await.map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => SourceError::NotFound(object.to_string()),
+            _ => SourceError::Backend(format!("{}: {}", path.display(), e)),
+        })
+    }
+
+    async fn list_tenants(&self) -> Result<Vec<String>, SourceError> {
+        let io_error = |e: std::io::Error| SourceError::Backend(format!("{}: {}", self.root.display(), e));
+
+        let mut tenants = Vec::new();
+        let mut dirs = tokio::fs::read_dir(&self.root).await.map_err(io_error)?;
+        while let Some(dir) = dirs.next_entry().await.map_err(io_error)? {
+            let Some(tenant) = dir
+                .file_name()
+                .to_str()
+                .and_then(|name| parse_gcs_object_name(&format!("{}/shard", name)))
+            else {
+                continue;
+            };
+            let shard = tokio::fs::metadata(dir.path().join("shard")).await;
+            if shard.is_ok_and(|metadata| metadata.is_file()) {
+                tenants.push(tenant);
+            }
+        }
+        Ok(tenants)
+    }
+
+    async fn read_versioned(&self, object: &str) -> Result<VersionedObject, SourceError> {
+        let contents = self.read_object(object).await?;
+        Ok(VersionedObject {
+            version: stable_hash(&[&contents]),
+            contents,
+        })
+    }
+
+    async fn write_object(
+        &self,
+        object: &str,
+        contents: &str,
+        precondition: Precondition,
+    ) -> Result<u64, SourceError> {
+        self.check_precondition(object, precondition).await?;
+
+        let path = self
+            .object_path(object)
+            .ok_or_else(|| SourceError::Backend(format!("invalid object name: {}", object)))?;
+        let io_error = |e: std::io::Error| SourceError::Backend(format!("{}: {}", path.display(), e));
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(io_error)?;
+        }
+        tokio::fs::write(&path, contents).await.map_err(io_error)?;
+
+        Ok(stable_hash(&[contents]))
+    }
+
+    async fn delete_object(&self, object: &str, precondition: Precondition) -> Result<(), SourceError> {
+        let current = self.read_versioned(object).await?;
+        if !precondition.holds(Some(current.version)) {
+            return Err(SourceError::PreconditionFailed(object.to_string()));
+        }
+
+        let path = self
+            .object_path(object)
+            .ok_or_else(|| SourceError::NotFound(object.to_string()))?;
+        tokio::fs::remove_file(&path).await.map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => SourceError::NotFound(object.to_string()),
+            _ => SourceError::Backend(format!("{}: {}", path.display(), e)),
+        })
+    }
+}
+
+impl LocalDirSource {
+    async fn check_precondition(
+        &self,
+        object: &str,
+        precondition: Precondition,
+    ) -> Result<(), SourceError> {
+        let version = match self.read_versioned(object).await {
+            Ok(current) => Some(current.version),
+            Err(SourceError::NotFound(_)) => None,
+            Err(e) => return Err(e),
+        };
+
+        if precondition.holds(version) {
+            Ok(())
+        } else {
+            Err(SourceError::PreconditionFailed(object.to_string()))
+        }
+    }
+}
+
+/// Keeps objects in memory. Useful for tests and local runs.
+#[derive(Default)]
+pub struct InMemorySource {
+    /// Contents and version of each object
+    objects: RwLock<HashMap<String, (String, u64)>>,
+    last_version: AtomicU64,
+}
+
+impl InMemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build a source from a `tenant=shard,tenant=shard` list.
+    ///
+    /// # Example
+    /// ```
+    /// use tenant_routing_core::source::InMemorySource;
+    /// let source = InMemorySource::from_spec("acme=shard2, corp=shard1").unwrap();
+    /// assert_eq!(source.len(), 2);
+    /// ```
+    pub fn from_spec(spec: &str) -> Result<Self, SourceError> {
+        let source = Self::new();
+
+        for pair in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            match pair.split_once('=') {
+                Some((tenant, shard)) if !tenant.trim().is_empty() => {
+                    source.insert_tenant(tenant.trim(), shard.trim());
+                }
+                _ => {
+                    return Err(SourceError::Backend(format!(
+                        "invalid in-memory mapping '{}', expected tenant=shard",
+                        pair
+                    )));
+                }
+            }
+        }
+
+        Ok(source)
+    }
+
+    pub fn insert(&self, object: impl Into<String>, contents: impl Into<String>) {
+        let version = self.next_version();
+        self.objects
+            .write()
+            .unwrap()
+            .insert(object.into(), (contents.into(), version));
+    }
+
+    fn next_version(&self) -> u64 {
+        self.last_version.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn insert_tenant(&self, tenant: &str, shard: &str) {
+        self.insert(build_gcs_object_name(tenant), shard);
+    }
+
+    pub fn insert_alias(&self, host: &str, tenant: &str) {
+        self.insert(build_alias_object_name(host), tenant);
+    }
+
+    pub fn set_generation(&self, generation: u64) {
+        self.insert(GENERATION_OBJECT, generation.to_string());
+    }
+
+    pub fn remove(&self, object: &str) -> Option<String> {
+        self.objects
+            .write()
+            .unwrap()
+            .remove(object)
+            .map(|(contents, _)| contents)
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[async_trait]
+impl MappingSource for InMemorySource {
+    fn describe(&self) -> String {
+        format!("memory ({} objects)", self.len())
+    }
+
+    async fn read_object(&self, object: &str) -> Result<String, SourceError> {
+        self.read_versioned(object).await.map(|object| object.contents)
+    }
+
+    async fn list_tenants(&self) -> Result<Vec<String>, SourceError> {
+        let objects = self.objects.read().unwrap();
+        Ok(objects.keys().filter_map(|object| parse_gcs_object_name(object)).collect())
+    }
+
+    async fn read_versioned(&self, object: &str) -> Result<VersionedObject, SourceError> {
+        self.objects
+            .read()
+            .unwrap()
+            .get(object)
+            .map(|(contents, version)| VersionedObject {
+                contents: contents.clone(),
+                version: *version,
+            })
+            .ok_or_else(|| SourceError::NotFound(object.to_string()))
+    }
+
+    async fn write_object(
+        &self,
+        object: &str,
+        contents: &str,
+        precondition: Precondition,
+    ) -> Result<u64, SourceError> {
+        let mut objects = self.objects.write().unwrap();
+        if !precondition.holds(objects.get(object).map(|(_, version)| *version)) {
+            return Err(SourceError::PreconditionFailed(object.to_string()));
+        }
+
+        let version = self.next_version();
+        objects.insert(object.to_string(), (contents.to_string(), version));
+        Ok(version)
+    }
+
+    async fn delete_object(&self, object: &str, precondition: Precondition) -> Result<(), SourceError> {
+        let mut objects = self.objects.write().unwrap();
+        let Some((_, version)) = objects.get(object) else {
+            return Err(SourceError::NotFound(object.to_string()));
+        };
+        if !precondition.holds(Some(*version)) {
+            return Err(SourceError::PreconditionFailed(object.to_string()));
+        }
+
+        objects.remove(object);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "gcs")]
+pub use gcs::GcsSource;
+
+#[cfg(feature = "gcs")]
+mod gcs {
+    use super::*;
+    use futures_util::{stream, StreamExt, TryStreamExt};
+    use google_cloud_storage::{
+        client::{Client, ClientConfig},
+        http::{
+            objects::{
+                delete::DeleteObjectRequest,
+                download::Range,
+                get::GetObjectRequest,
+                list::ListObjectsRequest,
+                upload::{Media, UploadObjectRequest, UploadType},
+            },
+            Error as GcsError,
+        },
+    };
+
+    /// Mapping objects checked at once while listing tenants.
+    const LIST_CHECK_CONCURRENCY: usize = 16;
+
+    /// Reads objects from a GCS bucket.
+    pub struct GcsSource {
+        client: Arc<Client>,
+        bucket: String,
+    }
+
+    impl GcsSource {
+        pub fn new(client: Arc<Client>, bucket: impl Into<String>) -> Self {
+            Self {
+                client,
+                bucket: bucket.into(),
+            }
+        }
+
+        /// Create a client using the default application credentials.
+        pub async fn connect(bucket: impl Into<String>) -> Result<Self, SourceError> {
+            let config = ClientConfig::default()
+                .with_auth()
+                .await
+                .map_err(|e| SourceError::Backend(format!("GCS auth failed: {}", e)))?;
+
+            Ok(Self::new(Arc::new(Client::new(config)), bucket))
+        }
+
+        pub fn bucket(&self) -> &str {
+            &self.bucket
+        }
+
+        /// Whether `object` exists, from its metadata.
+        async fn exists(&self, object: &str) -> Result<bool, SourceError> {
+            let request = GetObjectRequest {
+                bucket: self.bucket.clone(),
+                object: object.to_string(),
+                ..Default::default()
+            };
+
+            match self.client.get_object(&request).await {
+                Ok(_) => Ok(true),
+                Err(e) => match map_gcs_error(object, e) {
+                    SourceError::NotFound(_) => Ok(false),
+                    e => Err(e),
+                },
+            }
+        }
+    }
+
+    fn map_gcs_error(object: &str, error: GcsError) -> SourceError {
+        match error {
+            GcsError::Response(ref response) if response.code == 404 => {
+                SourceError::NotFound(object.to_string())
+            }
+            GcsError::Response(ref response) if response.code == 412 => {
+                SourceError::PreconditionFailed(object.to_string())
+            }
+            e => SourceError::Backend(e.to_string()),
+        }
+    }
+
+    #[async_trait]
+    impl MappingSource for GcsSource {
+        fn describe(&self) -> String {
+            format!("gs://{}", self.bucket)
+        }
+
+        async fn read_object(&self, object: &str) -> Result<String, SourceError> {
+            let request = GetObjectRequest {
+                bucket: self.bucket.clone(),
+                object: object.to_string(),
+                ..Default::default()
+            };
+
+            let bytes = self
+                .client
+                .download_object(&request, &Range::default())
+                .await
+                .map_err(|e| map_gcs_error(object, e))?;
+
+            String::from_utf8(bytes)
+                .map_err(|e| SourceError::Backend(format!("{} is not valid UTF-8: {}", object, e)))
+        }
+
+        async fn list_tenants(&self) -> Result<Vec<String>, SourceError> {
+            // Only the top level, where each tenant has a `<tenant>/`
+            // prefix, rather than every object in the bucket. Prefixes
+            // without a mapping object are then skipped
+            let mut request = ListObjectsRequest {
+                bucket: self.bucket.clone(),
+                delimiter: Some("/".to_string()),
+                ..Default::default()
+            };
+
+            let mut tenants = Vec::new();
+            loop {
+                let page = self
+                    .client
+                    .list_objects(&request)
+                    .await
+                    .map_err(|e| map_gcs_error(&self.bucket, e))?;
+                let candidates = page.prefixes.unwrap_or_default().into_iter().filter_map(|prefix| {
+                    let object = format!("{}shard", prefix);
+                    parse_gcs_object_name(&object).map(|tenant| (tenant, object))
+                });
+                let checked: Vec<(String, bool)> = stream::iter(candidates)
+                    .map(|(tenant, object)| async move {
+                        self.exists(&object).await.map(|exists| (tenant, exists))
+                    })
+                    .buffer_unordered(LIST_CHECK_CONCURRENCY)
+                    .try_collect()
+                    .await?;
+                tenants.extend(checked.into_iter().filter_map(|(tenant, exists)| exists.then_some(tenant)));
+
+                match page.next_page_token {
+                    Some(token) => request.page_token = Some(token),
+                    None => return Ok(tenants),
+                }
+            }
+        }
+
+        async fn read_versioned(&self, object: &str) -> Result<VersionedObject, SourceError> {
+            let mut request = GetObjectRequest {
+                bucket: self.bucket.clone(),
+                object: object.to_string(),
+                ..Default::default()
+            };
+            let metadata = self
+                .client
+                .get_object(&request)
+                .await
+                .map_err(|e| map_gcs_error(object, e))?;
+
+            // Download the generation the metadata describes, not a newer one
+            request.generation = Some(metadata.generation);
+            let bytes = self
+                .client
+                .download_object(&request, &Range::default())
+                .await
+                .map_err(|e| map_gcs_error(object, e))?;
+
+            let contents = String::from_utf8(bytes)
+                .map_err(|e| SourceError::Backend(format!("{} is not valid UTF-8: {}", object, e)))?;
+            Ok(VersionedObject {
+                contents,
+                version: metadata.generation as u64,
+            })
+        }
+
+        async fn write_object(
+            &self,
+            object: &str,
+            contents: &str,
+            precondition: Precondition,
+        ) -> Result<u64, SourceError> {
+            let request = UploadObjectRequest {
+                bucket: self.bucket.clone(),
+                if_generation_match: Some(generation_match(precondition)),
+                ..Default::default()
+            };
+            let mut media = Media::new(object.to_string());
+            media.content_type = "application/json".into();
+
+            let written = self
+                .client
+                .upload_object(&request, contents.to_string(), &UploadType::Simple(media))
+                .await
+                .map_err(|e| map_gcs_error(object, e))?;
+            Ok(written.generation as u64)
+        }
+
+        async fn delete_object(
+            &self,
+            object: &str,
+            precondition: Precondition,
+        ) -> Result<(), SourceError> {
+            let request = DeleteObjectRequest {
+                bucket: self.bucket.clone(),
+                object: object.to_string(),
+                if_generation_match: Some(generation_match(precondition)),
+                ..Default::default()
+            };
+
+            self.client
+                .delete_object(&request)
+                .await
+                .map_err(|e| map_gcs_error(object, e))
+        }
+    }
+
+    /// `ifGenerationMatch` value for `precondition`; GCS treats 0 as "the
+    /// object must not exist".
+    fn generation_match(precondition: Precondition) -> i64 {
+        match precondition {
+            Precondition::DoesNotExist => 0,
+            Precondition::Matches(version) => version as i64,
+        }
+    }
+}
+
+/// Which backend a binary should read mappings from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SourceConfig {
+    Gcs { bucket: String },
+    Local { root: PathBuf },
+    Memory { spec: String },
+}
+
+impl SourceConfig {
+    /// Read the backend selection from the environment.
+    ///
+    /// `MAPPING_SOURCE` picks `gcs` (default), `local` or `memory`. The local
+    /// backend reads `MAPPING_DIR`, the in-memory backend reads
+    /// `MAPPING_MEMORY` as a `tenant=shard,...` list.
+    pub fn from_env(bucket: &str) -> Result<Self, SourceError> {
+        let kind = std::env::var("MAPPING_SOURCE").unwrap_or_else(|_| "gcs".to_string());
+
+        match kind.trim().to_lowercase().as_str() {
+            "gcs" => Ok(SourceConfig::Gcs {
+                bucket: bucket.to_string(),
+            }),
+            "local" => std::env::var("MAPPING_DIR")
+                .map(|root| SourceConfig::Local { root: root.into() })
+                .map_err(|_| {
+                    SourceError::Backend("MAPPING_DIR must be set for the local source".into())
+                }),
+            "memory" => Ok(SourceConfig::Memory {
+                spec: std::env::var("MAPPING_MEMORY").unwrap_or_default(),
+            }),
+            other => Err(SourceError::Backend(format!(
+                "unknown MAPPING_SOURCE '{}', expected gcs, local or memory",
+                other
+            ))),
+        }
+    }
+
+    pub async fn build(&self) -> Result<Arc<dyn MappingSource>, SourceError> {
+        match self {
+            #[cfg(feature = "gcs")]
+            SourceConfig::Gcs { bucket } => Ok(Arc::new(GcsSource::connect(bucket.clone()).await?)),
+            #[cfg(not(feature = "gcs"))]
+            SourceConfig::Gcs { .. } => Err(SourceError::Backend(
+                "tenant-routing-core was built without the gcs feature".into(),
+            )),
+            SourceConfig::Local { root } => Ok(Arc::new(LocalDirSource::new(root.clone()))),
+            SourceConfig::Memory { spec } => Ok(Arc::new(InMemorySource::from_spec(spec)?)),
+        }
+    }
+}
@@ -1,22 +1,97 @@
 use alloc::format;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use serde::{Deserialize, Serialize};
 
-/// Extract tenant name from hostname
+use crate::alias::ALIAS_OBJECT_PREFIX;
+use crate::idna;
+
+/// Host part of an HTTP authority (`Host` header or `:authority`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Host {
+    /// A DNS name in lowercase ASCII (non-ASCII labels punycode-encoded),
+    /// without a trailing dot.
+    Domain(String),
+    /// An IPv4 or IPv6 literal. IPv6 literals may be bracketed.
+    Ip(IpAddr),
+}
+
+/// Parse an HTTP authority, dropping the port.
+///
+/// Returns `None` for malformed authorities, e.g. a non-numeric port, an
+/// unterminated IPv6 literal or an empty or invalid punycode label.
+///
+/// # Examples
+/// ```
+/// use tenant_routing_core::tenant::{parse_authority, Host};
+/// assert_eq!(parse_authority("Acme.Example.com.:8080"), Some(Host::Domain("acme.example.com".into())));
+/// assert!(matches!(parse_authority("[2001:db8::1]:8080"), Some(Host::Ip(_))));
+/// assert_eq!(parse_authority("acme.example.com:http"), None);
+/// ```
+pub fn parse_authority(authority: &str) -> Option<Host> {
+    let authority = authority.trim();
+
+    if let Some(rest) = authority.strip_prefix('[') {
+        let (literal, port) = rest.split_once(']')?;
+        let ip = literal.parse::<Ipv6Addr>().ok()?;
+
+        return match port.strip_prefix(':') {
+            Some(port) if is_valid_port(port) => Some(Host::Ip(IpAddr::V6(ip))),
+            None if port.is_empty() => Some(Host::Ip(IpAddr::V6(ip))),
+            _ => None,
+        };
+    }
+
+    // More than one colon can only be an unbracketed IPv6 literal
+    if authority.matches(':').count() > 1 {
+        return authority.parse::<Ipv6Addr>().ok().map(|ip| Host::Ip(IpAddr::V6(ip)));
+    }
+
+    let host = match authority.split_once(':') {
+        Some((host, port)) if is_valid_port(port) => host,
+        Some(_) => return None,
+        None => authority,
+    };
+
+    // A single trailing dot marks a fully qualified name
+    let host = host.strip_suffix('.').unwrap_or(host);
+    if host.is_empty() || host.ends_with('.') {
+        return None;
+    }
+
+    match host.parse::<Ipv4Addr>() {
+        Ok(ip) => Some(Host::Ip(IpAddr::V4(ip))),
+        Err(_) => idna::to_ascii(host).map(Host::Domain),
+    }
+}
+
+/// Ports may be empty (`host:`) per RFC 3986.
+fn is_valid_port(port: &str) -> bool {
+    port.is_empty() || (port.bytes().all(|b| b.is_ascii_digit()) && port.parse::<u16>().is_ok())
+}
+
+/// Extract tenant name from hostname. The tenant is returned as its
+/// canonical ID, see [`canonical_tenant_id`].
 ///
 /// # Examples
 /// - "tenant1.example.com" -> Some("tenant1")
-/// - "tenant1.example.com:8080" -> Some("tenant1")
+/// - "Tenant1.Example.com.:8080" -> Some("tenant1")
 /// - "localhost" -> None
 /// - "192.168.1.1" -> None
+/// - "[2001:db8::1]:8080" -> None
+/// - "münchen.example.com" -> Some("xn--mnchen-3ya")
 pub fn extract_tenant_from_host(host: &str) -> Option<String> {
-    let host_without_port = host.split(':').next().unwrap_or(host);
+    let host = match parse_authority(host)? {
+        Host::Domain(host) => host,
+        Host::Ip(_) => return None,
+    };
 
-    if is_ip_address(host_without_port) {
+    if is_ip_address(&host) {
         return None;
     }
 
-    let parts: Vec<&str> = host_without_port.split('.').collect();
+    let parts: Vec<&str> = host.split('.').collect();
 
     if parts.len() >= 2 {
         let tenant = parts[0];
@@ -31,15 +106,167 @@ pub fn extract_tenant_from_host(host: &str) -> Option<String> {
     }
 }
 
+fn default_tenant_labels() -> usize {
+    1
+}
+
+/// A domain tenants are served under, e.g. `example.com` for
+/// `acme.example.com`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct BaseDomain {
+    pub domain: String,
+    /// Number of labels directly under `domain` that make up the tenant, e.g.
+    /// 2 for `team.org.example.com` -> `team.org`.
+    #[serde(default = "default_tenant_labels")]
+    pub tenant_labels: usize,
+}
+
+impl BaseDomain {
+    pub fn new(domain: String) -> Self {
+        Self {
+            domain,
+            tenant_labels: default_tenant_labels(),
+        }
+    }
+
+    /// Parse a `domain[:labels]` list, e.g. `example.com,example.org:2`.
+    ///
+    /// # Example
+    /// ```
+    /// use tenant_routing_core::tenant::BaseDomain;
+    /// let domains = BaseDomain::from_spec("example.com, example.org:2").unwrap();
+    /// assert_eq!(domains[1].domain, "example.org");
+    /// assert_eq!(domains[1].tenant_labels, 2);
+    /// ```
+    pub fn from_spec(spec: &str) -> Result<Vec<Self>, String> {
+        spec.split(',')
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+            .map(|entry| {
+                let (domain, labels) = match entry.split_once(':') {
+                    Some((domain, labels)) => (domain, Some(labels)),
+                    None => (entry, None),
+                };
+                let mut base =
+                    BaseDomain::new(domain.trim().trim_matches('.').to_ascii_lowercase());
+
+                if let Some(labels) = labels {
+                    base.tenant_labels = labels.trim().parse().map_err(|_| {
+                        format!("invalid base domain entry '{}', expected domain[:labels]", entry)
+                    })?;
+                }
+
+                Ok(base)
+            })
+            .collect()
+    }
+}
+
+/// Extract the tenant from `host` using the configured base domains.
+///
+/// The tenant is the label (or `tenant_labels` labels) directly under the
+/// longest matching base domain, so deeper subdomains such as `www.` are
+/// ignored. Hosts outside every base domain have no tenant. With no base
+/// domains this is [`extract_tenant_from_host`].
+///
+/// # Examples
+/// ```
+/// use tenant_routing_core::tenant::{extract_tenant_for_domains, BaseDomain};
+/// let domains = [BaseDomain::new("example.com".into())];
+/// assert_eq!(extract_tenant_for_domains("www.acme.example.com", &domains), Some("acme".into()));
+/// assert_eq!(extract_tenant_for_domains("example.com", &domains), None);
+/// assert_eq!(extract_tenant_for_domains("acme.other.com", &domains), None);
+/// ```
+pub fn extract_tenant_for_domains(host: &str, base_domains: &[BaseDomain]) -> Option<String> {
+    if base_domains.is_empty() {
+        return extract_tenant_from_host(host);
+    }
+
+    let host = match parse_authority(host)? {
+        Host::Domain(host) => host,
+        Host::Ip(_) => return None,
+    };
+
+    let (base, subdomain) = base_domains
+        .iter()
+        .filter_map(|base| subdomain_of(&host, &base.domain).map(|subdomain| (base, subdomain)))
+        .max_by_key(|(_, subdomain)| core::cmp::Reverse(subdomain.len()))?;
+
+    let labels: Vec<&str> = subdomain.split('.').collect();
+    if base.tenant_labels == 0 || labels.len() < base.tenant_labels {
+        return None;
+    }
+
+    let tenant = &labels[labels.len() - base.tenant_labels..];
+    if tenant.iter().all(|label| is_valid_tenant_name(label)) {
+        Some(tenant.join("."))
+    } else {
+        None
+    }
+}
+
+/// The part of `host` in front of `domain`, compared case-insensitively.
+/// `host` must already be in ASCII form.
+fn subdomain_of<'a>(host: &'a str, domain: &str) -> Option<&'a str> {
+    let domain = domain.strip_suffix('.').unwrap_or(domain);
+    let ascii;
+    let domain = if domain.is_ascii() {
+        domain
+    } else {
+        ascii = idna::to_ascii(domain)?;
+        ascii.as_str()
+    };
+    let dot = host.len().checked_sub(domain.len() + 1)?;
+
+    let bytes = host.as_bytes();
+    if bytes[dot] == b'.' && bytes[dot + 1..].eq_ignore_ascii_case(domain.as_bytes()) {
+        Some(&host[..dot])
+    } else {
+        None
+    }
+}
+
 fn is_ip_address(s: &str) -> bool {
     s.split('.').all(|part| part.parse::<u32>().is_ok())
 }
 
+/// Check one ASCII tenant label. Punycode labels are checked by the
+/// characters they decode to.
 fn is_valid_tenant_name(name: &str) -> bool {
-    !name.is_empty()
-        && name
-            .chars()
-            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    let is_valid_char = |c: char| c.is_alphanumeric() || c == '-' || c == '_';
+
+    match idna::strip_ace_prefix(name) {
+        Some(_) => idna::label_to_unicode(name).is_some_and(|label| label.chars().all(is_valid_char)),
+        None => !name.is_empty() && name.chars().all(is_valid_char),
+    }
+}
+
+/// Canonical ID for a tenant name: lowercase, with non-ASCII labels
+/// punycode-encoded. This is the form used for cache keys, object names and
+/// headers. Use [`idna::to_unicode`](crate::idna::to_unicode) for display.
+///
+/// # Example
+/// ```
+/// use tenant_routing_core::tenant::canonical_tenant_id;
+/// assert_eq!(canonical_tenant_id("Bücher").as_deref(), Some("xn--bcher-kva"));
+/// assert_eq!(canonical_tenant_id("xn--bcher-kva").as_deref(), Some("xn--bcher-kva"));
+/// assert_eq!(canonical_tenant_id("acme!"), None);
+/// ```
+pub fn canonical_tenant_id(tenant: &str) -> Option<String> {
+    let id = idna::to_ascii(tenant.trim())?;
+
+    if id.split('.').all(is_valid_tenant_name) {
+        Some(id)
+    } else {
+        None
+    }
+}
+
+/// Tenant as it appears in object names: its canonical ID when it has one,
+/// otherwise unchanged.
+fn object_tenant(tenant: &str) -> String {
+    canonical_tenant_id(tenant).unwrap_or_else(|| tenant.to_string())
 }
 
 /// Build GCS path for tenant shard mapping
@@ -48,9 +275,10 @@ fn is_valid_tenant_name(name: &str) -> bool {
 /// ```
 /// use tenant_routing_core::tenant::build_gcs_path;
 /// assert_eq!(build_gcs_path("my-bucket", "tenant1"), "/my-bucket/tenant1/shard");
+/// assert_eq!(build_gcs_path("my-bucket", "münchen"), "/my-bucket/xn--mnchen-3ya/shard");
 /// ```
 pub fn build_gcs_path(bucket: &str, tenant: &str) -> String {
-    format!("/{}/{}/shard", bucket, tenant)
+    format!("/{}/{}/shard", bucket, object_tenant(tenant))
 }
 
 /// Build the object name for GCS (without leading slash)
@@ -61,9 +289,31 @@ pub fn build_gcs_path(bucket: &str, tenant: &str) -> String {
 /// assert_eq!(build_gcs_object_name("tenant1"), "tenant1/shard");
 /// ```
 pub fn build_gcs_object_name(tenant: &str) -> String {
-    format!("{}/shard", tenant)
+    format!("{}/shard", object_tenant(tenant))
+}
+
+/// Tenant whose mapping is stored in `object`, the reverse of
+/// [`build_gcs_object_name`]. `None` for any other object.
+///
+/// # Example
+/// ```
+/// use tenant_routing_core::tenant::parse_gcs_object_name;
+/// assert_eq!(parse_gcs_object_name("tenant1/shard"), Some("tenant1".to_string()));
+/// assert_eq!(parse_gcs_object_name("_meta/generation"), None);
+/// assert_eq!(parse_gcs_object_name("_meta/shard"), None);
+/// assert_eq!(parse_gcs_object_name("_aliases/shard"), None);
+/// ```
+pub fn parse_gcs_object_name(object: &str) -> Option<String> {
+    let tenant = object.strip_suffix("/shard")?;
+    if RESERVED_OBJECT_PREFIXES.contains(&tenant) {
+        return None;
+    }
+    canonical_tenant_id(tenant).filter(|id| id == tenant)
 }
 
+/// Top-level prefixes of bucket objects that are not tenant mappings.
+const RESERVED_OBJECT_PREFIXES: &[&str] = &[ALIAS_OBJECT_PREFIX, "_meta"];
+
 pub fn normalize_shard_name(shard: &str) -> String {
     shard.trim().to_lowercase()
 }
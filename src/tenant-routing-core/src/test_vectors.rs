@@ -0,0 +1,73 @@
+//! Host parsing cases every router must agree on.
+//!
+//! The WASM filter and the lookup service both resolve tenants with
+//! `TenantRoutingConfig::extract_tenant`; this crate's tests, the filter's
+//! tests and the lookup service's `/lookup` tests run every vector so they
+//! cannot drift. Other crates enable the `test-vectors` feature in their
+//! dev-dependencies to use them.
+
+pub struct HostVector {
+    /// Value of the `Host` header or `:authority` pseudo-header.
+    pub authority: &'static str,
+    /// Base domains as a `BaseDomain::from_spec` list; empty for none.
+    pub base_domains: &'static str,
+    /// Expected tenant, or `None` when the default shard applies.
+    pub tenant: Option<&'static str>,
+}
+
+const fn vector(
+    authority: &'static str,
+    base_domains: &'static str,
+    tenant: Option<&'static str>,
+) -> HostVector {
+    HostVector {
+        authority,
+        base_domains,
+        tenant,
+    }
+}
+
+pub const HOST_VECTORS: &[HostVector] = &[
+    // Plain hosts and ports
+    vector("tenant1.example.com", "", Some("tenant1")),
+    vector("tenant1.example.com:8080", "", Some("tenant1")),
+    vector("tenant1.example.com:", "", Some("tenant1")),
+    vector("tenant1.example.com:http", "", None),
+    vector("tenant1.example.com:99999", "", None),
+    vector("localhost", "", None),
+    vector("localhost:8080", "", None),
+    vector("", "", None),
+    vector(".example.com", "", None),
+    // Case and trailing dots
+    vector("TENANT1.Example.COM", "", Some("tenant1")),
+    vector("tenant1.example.com.", "", Some("tenant1")),
+    vector("tenant1.example.com.:8080", "", Some("tenant1")),
+    vector("tenant1.example.com..", "", None),
+    // IP literals
+    vector("192.168.1.1", "", None),
+    vector("10.0.0.1:8080", "", None),
+    vector("[2001:db8::1]", "", None),
+    vector("[2001:db8::1]:8080", "", None),
+    vector("[::1]:8080", "", None),
+    vector("2001:db8::1", "", None),
+    vector("[2001:db8::1", "", None),
+    vector("[2001:db8::1]8080", "", None),
+    vector("[not-an-ip]:8080", "", None),
+    // Internationalized names resolve to their punycode tenant ID
+    vector("münchen.example.com", "", Some("xn--mnchen-3ya")),
+    vector("MÜNCHEN.example.com", "", Some("xn--mnchen-3ya")),
+    vector("xn--mnchen-3ya.example.com", "", Some("xn--mnchen-3ya")),
+    vector("XN--MNCHEN-3YA.example.com:443", "", Some("xn--mnchen-3ya")),
+    vector("xn--mnchen-!.example.com", "", None),
+    vector("xn--acme.example.com", "", None),
+    // Base domains
+    vector("www.Acme.Example.com.", "example.com", Some("acme")),
+    vector("acme.example.com:8443", "Example.COM.", Some("acme")),
+    vector("example.com", "example.com", None),
+    vector("acme.other.com", "example.com", None),
+    vector("[2001:db8::1]:443", "example.com", None),
+    vector("team.org.example.com", "example.com:2", Some("team.org")),
+    vector("Team.Org.Example.com:443", "example.com:2", Some("team.org")),
+    vector("bücher.münchen.example", "münchen.example", Some("xn--bcher-kva")),
+    vector("xn--bcher-kva.xn--mnchen-3ya.example", "münchen.example", Some("xn--bcher-kva")),
+];
@@ -1,6 +1,11 @@
 #[cfg(test)]
 mod config_tests {
-    use crate::config::TenantRoutingConfig;
+    use crate::config::{PrewarmMode, TenantRoutingConfig};
+    use crate::error::*;
+
+    fn field_errors(config: &TenantRoutingConfig) -> Vec<FieldError> {
+        config.validate().unwrap_err().errors().to_vec()
+    }
 
     #[test]
     fn test_default_config() {
@@ -24,21 +29,149 @@ mod config_tests {
             300,
             "shard1".to_string(),
         );
-        assert_eq!(invalid_bucket.validate(), Err("GCS bucket name cannot be empty"));
+        assert_eq!(
+            field_errors(&invalid_bucket),
+            vec![FieldError::new("gcs_bucket", ValidationError::Empty)]
+        );
 
         let invalid_shard = TenantRoutingConfig::new(
             "my-bucket".to_string(),
             300,
             "".to_string(),
         );
-        assert_eq!(invalid_shard.validate(), Err("Default shard cannot be empty"));
+        assert_eq!(
+            field_errors(&invalid_shard),
+            vec![FieldError::new("default_shard", ValidationError::Empty)]
+        );
 
         let invalid_ttl = TenantRoutingConfig::new(
             "my-bucket".to_string(),
             0,
             "shard1".to_string(),
         );
-        assert_eq!(invalid_ttl.validate(), Err("Cache TTL must be greater than 0"));
+        assert_eq!(
+            field_errors(&invalid_ttl),
+            vec![FieldError::new("cache_ttl_seconds", ValidationError::Zero)]
+        );
+
+        let invalid_fallback = TenantRoutingConfig {
+            fallback_shards: vec!["shard1".to_string(), "".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            field_errors(&invalid_fallback),
+            vec![FieldError::new("fallback_shards[1]", ValidationError::Empty)]
+        );
+    }
+
+    #[test]
+    fn test_config_validation_percentages() {
+        let config = TenantRoutingConfig {
+            cache_ttl_jitter_percent: 101,
+            refresh_ahead_percent: 150,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            config.validate().unwrap_err().errors(),
+            &[
+                FieldError::new("cache_ttl_jitter_percent", ValidationError::AboveMaximum(100)),
+                FieldError::new("refresh_ahead_percent", ValidationError::AboveMaximum(100)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config_validation_collects_all_errors() {
+        let config = TenantRoutingConfig::new(String::new(), 0, String::new());
+        let error = config.validate().unwrap_err();
+
+        assert_eq!(
+            error.errors(),
+            &[
+                FieldError::new("gcs_bucket", ValidationError::Empty),
+                FieldError::new("cache_ttl_seconds", ValidationError::Zero),
+                FieldError::new("default_shard", ValidationError::Empty),
+            ]
+        );
+        assert_eq!(
+            error.to_string(),
+            "invalid configuration: gcs_bucket cannot be empty; \
+             cache_ttl_seconds must be greater than 0; default_shard cannot be empty"
+        );
+    }
+
+    #[test]
+    fn test_prewarm_validation() {
+        let mut config = TenantRoutingConfig {
+            prewarm: PrewarmMode::Tenants,
+            prewarm_concurrency: 0,
+            ..Default::default()
+        };
+        assert_eq!(
+            field_errors(&config),
+            vec![
+                FieldError::new("prewarm_tenants", ValidationError::Empty),
+                FieldError::new("prewarm_concurrency", ValidationError::Zero),
+            ]
+        );
+
+        config.prewarm_concurrency = 4;
+        config.prewarm_tenants = vec!["acme".to_string(), "not a tenant".to_string()];
+        assert_eq!(
+            field_errors(&config),
+            vec![FieldError::new(
+                "prewarm_tenants[1]",
+                ValidationError::InvalidTenant("not a tenant".to_string())
+            )]
+        );
+    }
+
+    #[test]
+    fn test_fallback_shard_defaults() {
+        let config = TenantRoutingConfig::default();
+        assert!(!config.uses_hash_fallback());
+        assert_eq!(config.fallback_shard("tenant1"), "shard1");
+    }
+
+    #[test]
+    fn test_fallback_shard_hashing() {
+        let mut config = TenantRoutingConfig {
+            fallback_shards: vec!["shard1".to_string(), "shard2".to_string(), "shard3".to_string()],
+            ..Default::default()
+        };
+        assert!(config.uses_hash_fallback());
+
+        let tenants: Vec<String> = (0..3000).map(|i| format!("tenant{}", i)).collect();
+        let before: Vec<String> = tenants
+            .iter()
+            .map(|t| config.fallback_shard(t).to_string())
+            .collect();
+
+        // Spread roughly evenly
+        for shard in &config.fallback_shards {
+            let count = before.iter().filter(|s| *s == shard).count();
+            assert!((800..1200).contains(&count), "{} = {}", shard, count);
+        }
+
+        // Order of the configured shards does not matter
+        let mut reordered = config.clone();
+        reordered.fallback_shards.reverse();
+        for (tenant, shard) in tenants.iter().zip(&before) {
+            assert_eq!(reordered.fallback_shard(tenant), shard);
+        }
+
+        // Adding a shard only moves tenants onto the new shard
+        config.fallback_shards.push("shard4".to_string());
+        let mut moved = 0;
+        for (tenant, shard) in tenants.iter().zip(&before) {
+            let after = config.fallback_shard(tenant);
+            if after != shard {
+                assert_eq!(after, "shard4");
+                moved += 1;
+            }
+        }
+        assert!((600..900).contains(&moved), "moved = {}", moved);
     }
 }
 
@@ -90,6 +223,8 @@ mod tenant_tests {
 #[cfg(test)]
 mod cache_tests {
     use crate::cache::*;
+    use crate::config::TenantRoutingConfig;
+    use crate::mapping::MappingRecord;
 
     #[test]
     fn test_cache_entry_creation() {
@@ -110,6 +245,140 @@ mod cache_tests {
         assert!(!entry.is_valid(1001));
     }
 
+    #[test]
+    fn test_cache_entry_freshness() {
+        let entry = CacheEntry::with_ttl("shard1".to_string(), 1000, 300).with_stale_windows(60, 600);
+        assert_eq!(entry.freshness(1000), Freshness::Fresh);
+        assert_eq!(entry.freshness(1300), Freshness::Revalidate);
+        assert_eq!(entry.freshness(1359), Freshness::Revalidate);
+        assert_eq!(entry.freshness(1360), Freshness::Stale);
+        assert_eq!(entry.freshness(1899), Freshness::Stale);
+        assert_eq!(entry.freshness(1900), Freshness::Expired);
+        assert_eq!(entry.hard_expiry(), 1900);
+
+        // The revalidate window never outlasts the hard expiry
+        let capped = CacheEntry::with_ttl("shard1".to_string(), 1000, 300).with_stale_windows(600, 60);
+        assert_eq!(capped.freshness(1359), Freshness::Revalidate);
+        assert_eq!(capped.freshness(1360), Freshness::Expired);
+
+        // Entries without stale windows expire at the soft expiry
+        let plain = CacheEntry::with_ttl("shard1".to_string(), 1000, 300);
+        assert_eq!(plain.freshness(1300), Freshness::Expired);
+
+        let json = serde_json::to_string(&entry).unwrap();
+        assert_eq!(serde_json::from_str::<CacheEntry>(&json).unwrap(), entry);
+    }
+
+    #[test]
+    fn test_jittered_ttl() {
+        let ttls: Vec<u64> = (0..100)
+            .map(|i| jittered_ttl(&format!("tenant-{}", i), 300, 10))
+            .collect();
+
+        assert!(ttls.iter().all(|ttl| (270..=300).contains(ttl)));
+        // Spread out rather than all expiring together
+        let distinct: std::collections::HashSet<_> = ttls.iter().collect();
+        assert!(distinct.len() > 10);
+
+        assert_eq!(jittered_ttl("acme", 300, 10), jittered_ttl("acme", 300, 10));
+        assert_eq!(jittered_ttl("acme", 300, 0), 300);
+        assert!(jittered_ttl("acme", 300, 250) <= 300);
+    }
+
+    #[test]
+    fn test_refresh_ahead() {
+        let config = TenantRoutingConfig {
+            cache_ttl_seconds: 100,
+            cache_ttl_jitter_percent: 0,
+            refresh_ahead_percent: 80,
+            ..Default::default()
+        };
+        let mut entry = config.cache_entry("acme", MappingRecord::new("shard1".to_string()), 1000);
+
+        assert_eq!(entry.expiry, 1100);
+        assert_eq!(entry.freshness(1079), Freshness::Fresh);
+        assert_eq!(entry.freshness(1080), Freshness::RefreshAhead);
+        assert_eq!(entry.freshness(1100), Freshness::Revalidate);
+
+        // One refresh at a time
+        entry.postpone_refresh(1085);
+        assert_eq!(entry.freshness(1080), Freshness::Fresh);
+        assert_eq!(entry.freshness(1085), Freshness::RefreshAhead);
+
+        // Postponing past the soft expiry does not extend it
+        entry.postpone_refresh(1105);
+        assert_eq!(entry.freshness(1099), Freshness::Fresh);
+        assert_eq!(entry.freshness(1100), Freshness::Revalidate);
+        assert!(entry.refresh_postponed(1104));
+        assert!(!entry.refresh_postponed(1105));
+
+        let config = TenantRoutingConfig {
+            refresh_ahead_percent: 0,
+            ..config
+        };
+        let entry = config.cache_entry("acme", MappingRecord::new("shard1".to_string()), 1000);
+        assert_eq!(entry.freshness(1099), Freshness::Fresh);
+    }
+
+    #[test]
+    fn test_claimed_refresh_keeps_stale_windows() {
+        let entry = CacheEntry::with_ttl("shard1".to_string(), 1000, 300).with_stale_windows(60, 600);
+
+        let mut revalidating = entry.clone();
+        revalidating.postpone_refresh(1305);
+        assert_eq!(revalidating.freshness(1300), Freshness::Revalidate);
+        assert!(revalidating.refresh_postponed(1300));
+
+        let mut stale = entry.clone();
+        stale.postpone_refresh(1905);
+        assert_eq!(stale.freshness(1400), Freshness::Stale);
+        assert_eq!(stale.freshness(1900), Freshness::Expired);
+        assert_eq!(stale.expiry, entry.expiry);
+        assert_eq!(stale.hard_expiry(), entry.hard_expiry());
+    }
+
+    #[test]
+    fn test_cache_entry_generation() {
+        // Entries written before the first bump read as generation 0
+        let entry: CacheEntry = serde_json::from_str(r#"{"shard":"shard1","expiry":100}"#).unwrap();
+        assert_eq!(entry.generation, 0);
+        assert!(!serde_json::to_string(&entry).unwrap().contains("generation"));
+
+        let mut alias = AliasCacheEntry::with_ttl(Some("acme".to_string()), 0, 60);
+        alias.generation = 3;
+        let json = serde_json::to_string(&alias).unwrap();
+        assert_eq!(serde_json::from_str::<AliasCacheEntry>(&json).unwrap().generation, 3);
+
+        let negative: NegativeCacheEntry = serde_json::from_str(r#"{"expiry":100}"#).unwrap();
+        assert_eq!(negative.generation, 0);
+        assert_eq!(parse_cache_key("g3:tenant:acme"), None);
+
+        assert_eq!(parse_generation_record(" 12 "), Ok(12));
+        assert_eq!(parse_generation_record(r#"{"generation":3}"#), Ok(3));
+        assert_eq!(
+            parse_generation_record("-1"),
+            Err(crate::mapping::MappingError::InvalidGeneration("-1".to_string()))
+        );
+
+        let entry = GenerationCacheEntry::with_ttl(3, 1000, 10);
+        assert!(entry.is_valid(1009));
+        assert!(!entry.is_valid(1010));
+    }
+
+    #[test]
+    fn test_negative_cache_entry() {
+        let entry = NegativeCacheEntry::with_ttl(1000, 30);
+        assert!(entry.is_valid(1029));
+        assert!(!entry.is_valid(1030));
+
+        let json = serde_json::to_string(&entry).unwrap();
+        assert_eq!(serde_json::from_str::<NegativeCacheEntry>(&json).unwrap(), entry);
+
+        // Kept apart from mapping entries
+        assert_eq!(generate_negative_cache_key("acme"), "missing:acme");
+        assert_eq!(parse_cache_key("missing:acme"), None);
+    }
+
     #[test]
     fn test_cache_entry_with_ttl() {
         let current_time = 1000;
@@ -149,4 +418,1232 @@ mod cache_tests {
         let deserialized: CacheEntry = serde_json::from_str(&json).unwrap();
         assert_eq!(deserialized, entry);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod mapping_tests {
+    use crate::mapping::*;
+
+    #[test]
+    fn test_parse_legacy_record() {
+        let record = parse_mapping_record("  SHARD2\n").unwrap();
+        assert_eq!(record, MappingRecord::new("shard2".to_string()));
+
+        assert_eq!(parse_mapping_record(""), Err(MappingError::EmptyShard));
+        assert_eq!(parse_mapping_record(" \n"), Err(MappingError::EmptyShard));
+    }
+
+    #[test]
+    fn test_parse_json_record() {
+        let record = parse_mapping_record(
+            r#"{
+                "schema_version": 1,
+                "shard": " Shard3 ",
+                "metadata": {"tier": "gold", "region": "us-central1", "labels": {"team": "core"}},
+                "updated_at": 1700000000
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(record.schema_version, 1);
+        assert_eq!(record.shard, "shard3");
+        assert_eq!(record.metadata.tier.as_deref(), Some("gold"));
+        assert_eq!(record.metadata.region.as_deref(), Some("us-central1"));
+        assert_eq!(record.metadata.labels.get("team").map(String::as_str), Some("core"));
+        assert_eq!(record.updated_at, Some(1700000000));
+
+        // Only the shard is required
+        let minimal = parse_mapping_record(r#"{"shard":"shard1"}"#).unwrap();
+        assert_eq!(minimal, MappingRecord::new("shard1".to_string()));
+    }
+
+    #[test]
+    fn test_parse_invalid_json_record() {
+        assert!(matches!(
+            parse_mapping_record(r#"{"shard":"#),
+            Err(MappingError::InvalidJson(_))
+        ));
+        assert!(matches!(
+            parse_mapping_record(r#"{"tier":"gold"}"#),
+            Err(MappingError::InvalidJson(_))
+        ));
+        assert_eq!(
+            parse_mapping_record(r#"{"shard":""}"#),
+            Err(MappingError::EmptyShard)
+        );
+        assert_eq!(
+            parse_mapping_record(r#"{"schema_version":2,"shard":"shard1"}"#),
+            Err(MappingError::UnsupportedVersion(2))
+        );
+    }
+
+    #[test]
+    fn test_mapping_record_serialization() {
+        let record = MappingRecord::new("shard1".to_string());
+        let json = serde_json::to_string(&record).unwrap();
+        assert_eq!(json, r#"{"schema_version":1,"shard":"shard1"}"#);
+        assert_eq!(parse_mapping_record(&json).unwrap(), record);
+    }
+}
+
+#[cfg(test)]
+mod split_tests {
+    use crate::cache::CacheEntry;
+    use crate::hash::stable_hash;
+    use crate::mapping::*;
+    use crate::migration::{MigrationPhase, MigrationState};
+
+    fn split_record() -> MappingRecord {
+        parse_mapping_record(
+            r#"{"shard":"shard1","splits":[{"shard":"shard1","weight":90},{"shard":"SHARD2","weight":10}]}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_stable_hash_is_fixed() {
+        // Routing depends on these values staying the same across releases
+        assert_eq!(stable_hash(&[]), stable_hash(&[""]));
+        assert_eq!(stable_hash(&["tenant1", "key"]), stable_hash(&["tenant1", "key"]));
+        assert_ne!(stable_hash(&["tenant1", "key"]), stable_hash(&["tenant1key"]));
+        assert_eq!(stable_hash(&["tenant1"]), 0xdeef_6cce_a41d_cd64);
+    }
+
+    #[test]
+    fn test_parse_splits() {
+        let record = split_record();
+        assert_eq!(
+            record.splits,
+            vec![
+                WeightedShard::new("shard1".to_string(), 90),
+                WeightedShard::new("shard2".to_string(), 10),
+            ]
+        );
+
+        assert_eq!(
+            parse_mapping_record(r#"{"shard":"shard1","splits":[{"shard":"shard2","weight":0}]}"#),
+            Err(MappingError::ZeroSplitWeight)
+        );
+        assert_eq!(
+            parse_mapping_record(r#"{"shard":"shard1","splits":[{"shard":" ","weight":5}]}"#),
+            Err(MappingError::EmptyShard)
+        );
+    }
+
+    #[test]
+    fn test_select_shard_is_deterministic() {
+        let record = split_record();
+        for i in 0..100 {
+            let key = format!("session-{}", i);
+            let first = record.select_shard("acme", &key);
+            assert_eq!(record.select_shard("acme", &key), first);
+        }
+
+        // Without splits the primary shard is always used
+        let plain = MappingRecord::new("shard3".to_string());
+        assert_eq!(plain.select_shard("acme", "session-1"), "shard3");
+    }
+
+    #[test]
+    fn test_select_shard_respects_weights() {
+        let record = split_record();
+        let moved = (0..10_000)
+            .filter(|i| record.select_shard("acme", &format!("session-{}", i)) == "shard2")
+            .count();
+
+        // 10% of 10k keys, allowing for hash variance
+        assert!((800..1200).contains(&moved), "moved = {}", moved);
+    }
+
+    #[test]
+    fn test_cache_entry_keeps_splits() {
+        let record = split_record();
+        let entry = CacheEntry::from_record(record.clone(), 1000, 300);
+        assert_eq!(entry.expiry, 1300);
+        assert_eq!(entry.splits, record.splits);
+
+        let entry: CacheEntry = serde_json::from_str(&serde_json::to_string(&entry).unwrap()).unwrap();
+        for i in 0..100 {
+            let key = format!("session-{}", i);
+            assert_eq!(entry.select_shard("acme", &key), record.select_shard("acme", &key));
+        }
+
+        // Entries written before splits existed still deserialize
+        let legacy: CacheEntry = serde_json::from_str(r#"{"shard":"shard1","expiry":1000}"#).unwrap();
+        assert_eq!(legacy, CacheEntry::new("shard1".to_string(), 1000));
+    }
+
+    #[test]
+    fn test_cache_entry_routes_to() {
+        let entry = CacheEntry::from_record(split_record(), 1000, 300);
+        assert_eq!(entry.cached_at, 1000);
+        for split in &entry.splits {
+            assert!(entry.routes_to(&split.shard));
+        }
+        assert!(entry.routes_to(&entry.shard));
+        assert!(!entry.routes_to("shard9"));
+
+        let mut entry = CacheEntry::with_ttl("shard1".to_string(), 1000, 300);
+        entry.migration = Some(MigrationState::new(
+            "shard1".to_string(),
+            "shard2".to_string(),
+            MigrationPhase::Draining,
+        ));
+        assert!(entry.routes_to("shard2"));
+        assert!(!entry.routes_to("shard3"));
+    }
+}
+
+#[cfg(test)]
+mod migration_tests {
+    use crate::cache::CacheEntry;
+    use crate::mapping::*;
+    use crate::migration::*;
+
+    fn ctx(now: u64, is_write: bool) -> RequestContext<'static> {
+        RequestContext {
+            tenant: "acme",
+            key: "session-1",
+            is_write,
+            now,
+        }
+    }
+
+    fn migrating(phase: &str, effective_at: u64) -> MappingRecord {
+        parse_mapping_record(&format!(
+            r#"{{"shard":"shard1","migration":{{"source_shard":"Shard1","target_shard":"shard2","phase":"{}","effective_at":{}}}}}"#,
+            phase, effective_at
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_migration() {
+        let record = migrating("read_only", 1000);
+        let migration = record.migration.unwrap();
+        assert_eq!(migration.source_shard, "shard1");
+        assert_eq!(migration.target_shard, "shard2");
+        assert_eq!(migration.phase, MigrationPhase::ReadOnly);
+        assert_eq!(migration.effective_at, 1000);
+        assert_eq!(migration.retry_after_seconds, 30);
+
+        assert!(parse_mapping_record(
+            r#"{"shard":"shard1","migration":{"source_shard":"shard1","target_shard":"shard2","phase":"paused"}}"#
+        )
+        .is_err());
+        assert_eq!(
+            parse_mapping_record(
+                r#"{"shard":"shard1","migration":{"source_shard":"shard1","target_shard":"","phase":"moved"}}"#
+            ),
+            Err(MappingError::EmptyShard)
+        );
+    }
+
+    #[test]
+    fn test_route_without_migration() {
+        let record = MappingRecord::new("shard3".to_string());
+        assert_eq!(
+            record.route(&ctx(0, true)),
+            RouteDecision::Forward {
+                shard: "shard3",
+                phase: None
+            }
+        );
+    }
+
+    #[test]
+    fn test_route_by_phase() {
+        for phase in ["active", "draining"] {
+            let record = migrating(phase, 0);
+            for is_write in [false, true] {
+                assert!(matches!(
+                    record.route(&ctx(10, is_write)),
+                    RouteDecision::Forward { shard: "shard1", phase: Some(_) }
+                ));
+            }
+        }
+
+        let frozen = migrating("read_only", 0);
+        assert_eq!(
+            frozen.route(&ctx(10, false)),
+            RouteDecision::Forward {
+                shard: "shard1",
+                phase: Some(MigrationPhase::ReadOnly)
+            }
+        );
+        assert_eq!(
+            frozen.route(&ctx(10, true)),
+            RouteDecision::Unavailable {
+                phase: MigrationPhase::ReadOnly,
+                retry_after_seconds: 30
+            }
+        );
+
+        let moved = migrating("moved", 0);
+        for is_write in [false, true] {
+            assert_eq!(
+                moved.route(&ctx(10, is_write)),
+                RouteDecision::Forward {
+                    shard: "shard2",
+                    phase: Some(MigrationPhase::Moved)
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_phase_waits_for_effective_at() {
+        // Writes keep going to the source until the scheduled cutover
+        let record = migrating("moved", 1000);
+        assert_eq!(
+            record.route(&ctx(999, false)),
+            RouteDecision::Forward {
+                shard: "shard1",
+                phase: Some(MigrationPhase::ReadOnly)
+            }
+        );
+        assert!(matches!(
+            record.route(&ctx(999, true)),
+            RouteDecision::Unavailable { .. }
+        ));
+        assert_eq!(
+            record.route(&ctx(1000, true)),
+            RouteDecision::Forward {
+                shard: "shard2",
+                phase: Some(MigrationPhase::Moved)
+            }
+        );
+
+        let draining = migrating("draining", 1000);
+        assert_eq!(draining.migration.unwrap().current_phase(999), MigrationPhase::Active);
+    }
+
+    #[test]
+    fn test_cache_entry_routes_like_record() {
+        let record = migrating("read_only", 500);
+        let entry = CacheEntry::from_record(record.clone(), 0, 300);
+        let entry: CacheEntry = serde_json::from_str(&serde_json::to_string(&entry).unwrap()).unwrap();
+
+        for now in [0, 499, 500, 1000] {
+            for is_write in [false, true] {
+                assert_eq!(entry.route(&ctx(now, is_write)), record.route(&ctx(now, is_write)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_is_write_method() {
+        for method in ["GET", "HEAD", "OPTIONS", "TRACE", "get"] {
+            assert!(!is_write_method(method));
+        }
+        for method in ["POST", "PUT", "PATCH", "DELETE", "delete"] {
+            assert!(is_write_method(method));
+        }
+    }
+}
+
+#[cfg(test)]
+mod registry_tests {
+    use crate::config::TenantRoutingConfig;
+    use crate::error::*;
+    use crate::hash::weighted_rendezvous_pick;
+    use crate::mapping::*;
+    use crate::registry::*;
+
+    fn config_with_registry(spec: &str) -> TenantRoutingConfig {
+        TenantRoutingConfig {
+            shards: ShardRegistry::from_spec(spec).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_registry_from_spec() {
+        let registry = ShardRegistry::from_spec("shard1, SHARD2:3, shard3:0:shard1").unwrap();
+        assert_eq!(
+            registry.shards(),
+            &[
+                ShardInfo::new("shard1".to_string()),
+                ShardInfo {
+                    name: "shard2".to_string(),
+                    failover: None,
+                    capacity_weight: 3
+                },
+                ShardInfo {
+                    name: "shard3".to_string(),
+                    failover: Some("shard1".to_string()),
+                    capacity_weight: 0
+                },
+            ]
+        );
+
+        assert!(ShardRegistry::from_spec("").unwrap().is_empty());
+        assert!(ShardRegistry::from_spec("shard1:heavy").is_err());
+        assert!(ShardRegistry::from_spec(":2").is_err());
+        assert!(ShardRegistry::from_spec("shard1:1:shard2:extra").is_err());
+    }
+
+    #[test]
+    fn test_registry_deserialization() {
+        let registry: ShardRegistry = serde_json::from_str(
+            r#"[{"name":"shard1"},{"name":"shard2","failover":"shard1","capacity_weight":0}]"#,
+        )
+        .unwrap();
+        assert_eq!(registry.capacity_weight("shard1"), 1);
+        assert_eq!(registry.capacity_weight("shard2"), 0);
+        assert_eq!(registry.resolve("shard2"), Ok("shard1"));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let registry = ShardRegistry::from_spec("shard1,shard2:0:shard1,shard3:0").unwrap();
+        assert_eq!(registry.resolve("shard1"), Ok("shard1"));
+        assert_eq!(registry.resolve("shard2"), Ok("shard1"));
+        // Out of rotation without a failover still routes to itself
+        assert_eq!(registry.resolve("shard3"), Ok("shard3"));
+        assert_eq!(
+            registry.resolve("shard9"),
+            Err(MappingError::UnknownShard("shard9".to_string()))
+        );
+
+        // An empty registry accepts anything
+        assert_eq!(ShardRegistry::default().resolve("shard9"), Ok("shard9"));
+    }
+
+    #[test]
+    fn test_parse_mapping_rejects_unknown_shards() {
+        let config = config_with_registry("shard1,shard2");
+        assert_eq!(config.parse_mapping("shard2").unwrap().shard, "shard2");
+        assert_eq!(
+            config.parse_mapping("shard9"),
+            Err(MappingError::UnknownShard("shard9".to_string()))
+        );
+        assert_eq!(
+            config.parse_mapping(
+                r#"{"shard":"shard1","splits":[{"shard":"shard1","weight":1},{"shard":"shard9","weight":1}]}"#
+            ),
+            Err(MappingError::UnknownShard("shard9".to_string()))
+        );
+        assert_eq!(
+            config.parse_mapping(
+                r#"{"shard":"shard1","migration":{"source_shard":"shard1","target_shard":"shard9","phase":"active"}}"#
+            ),
+            Err(MappingError::UnknownShard("shard9".to_string()))
+        );
+
+        // Without a registry every shard is accepted
+        assert!(TenantRoutingConfig::default().parse_mapping("shard9").is_ok());
+    }
+
+    #[test]
+    fn test_config_validation_with_registry() {
+        let field_errors =
+            |config: &TenantRoutingConfig| config.validate().unwrap_err().errors().to_vec();
+
+        assert!(config_with_registry("shard1,shard2:0:shard1").validate().is_ok());
+        assert_eq!(
+            field_errors(&config_with_registry("shard2")),
+            vec![FieldError::new(
+                "default_shard",
+                ValidationError::UnknownShard("shard1".to_string())
+            )]
+        );
+        assert_eq!(
+            field_errors(&config_with_registry("shard1,shard2:0:shard3,shard1")),
+            vec![
+                FieldError::new(
+                    "shards[1].failover",
+                    ValidationError::InvalidFailover("shard3".to_string())
+                ),
+                FieldError::new(
+                    "shards[2].name",
+                    ValidationError::DuplicateShard("shard1".to_string())
+                ),
+            ]
+        );
+
+        assert!(config_with_registry("shard1:10000").validate().is_ok());
+        assert_eq!(
+            field_errors(&config_with_registry("shard1:10001")),
+            vec![FieldError::new(
+                "shards[0].capacity_weight",
+                ValidationError::AboveMaximum(10_000)
+            )]
+        );
+
+        let mut config = config_with_registry("shard1,shard2");
+        config.fallback_shards = vec!["shard1".to_string(), "shard3".to_string()];
+        assert_eq!(
+            field_errors(&config),
+            vec![FieldError::new(
+                "fallback_shards[1]",
+                ValidationError::UnknownShard("shard3".to_string())
+            )]
+        );
+    }
+
+    #[test]
+    fn test_fallback_respects_capacity_weight() {
+        let mut config = config_with_registry("shard1:1,shard2:3,shard3:0");
+        config.fallback_shards = vec!["shard1".to_string(), "shard2".to_string(), "shard3".to_string()];
+
+        let picks: Vec<&str> = (0..4000)
+            .map(|i| config.fallback_shard(&format!("tenant{}", i)))
+            .collect();
+        let shard1 = picks.iter().filter(|s| **s == "shard1").count();
+        let shard2 = picks.iter().filter(|s| **s == "shard2").count();
+
+        assert!(!picks.contains(&"shard3"));
+        assert!((800..1200).contains(&shard1), "shard1 = {}", shard1);
+        assert!((2800..3200).contains(&shard2), "shard2 = {}", shard2);
+
+        // Weight 1 everywhere matches the unweighted placement
+        let mut unweighted = config_with_registry("shard1,shard2");
+        unweighted.fallback_shards = vec!["shard1".to_string(), "shard2".to_string()];
+        let plain = TenantRoutingConfig {
+            fallback_shards: unweighted.fallback_shards.clone(),
+            ..Default::default()
+        };
+        for i in 0..100 {
+            let tenant = format!("tenant{}", i);
+            assert_eq!(unweighted.fallback_shard(&tenant), plain.fallback_shard(&tenant));
+        }
+
+        // Scoring costs one hash per shard whatever the weight
+        let heavy = [("shard1", u32::MAX), ("shard2", 1)];
+        let picks = (0..1000)
+            .filter(|i| weighted_rendezvous_pick(&format!("tenant{}", i), heavy) == Some("shard2"))
+            .count();
+        assert!(picks < 5, "shard2 = {}", picks);
+    }
+}
+
+#[cfg(test)]
+mod base_domain_tests {
+    use crate::config::TenantRoutingConfig;
+    use crate::error::*;
+    use crate::tenant::*;
+
+    fn config_with_domains(spec: &str) -> TenantRoutingConfig {
+        TenantRoutingConfig {
+            base_domains: BaseDomain::from_spec(spec).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_base_domain_from_spec() {
+        let domains = BaseDomain::from_spec(" example.com, .example.org.:2 ,").unwrap();
+        assert_eq!(
+            domains,
+            vec![
+                BaseDomain::new("example.com".to_string()),
+                BaseDomain {
+                    domain: "example.org".to_string(),
+                    tenant_labels: 2
+                },
+            ]
+        );
+
+        assert!(BaseDomain::from_spec("example.com:two").is_err());
+    }
+
+    #[test]
+    fn test_label_directly_under_base_domain() {
+        let config = config_with_domains("example.com");
+
+        assert_eq!(config.extract_tenant("acme.example.com"), Some("acme".to_string()));
+        assert_eq!(config.extract_tenant("www.acme.example.com"), Some("acme".to_string()));
+        assert_eq!(config.extract_tenant("acme.example.com:8443"), Some("acme".to_string()));
+        assert_eq!(config.extract_tenant("example.com"), None);
+        assert_eq!(config.extract_tenant("www.example.com.evil.net"), None);
+        assert_eq!(config.extract_tenant("acme.notexample.com"), None);
+        assert_eq!(config.extract_tenant("acme.other.com"), None);
+        assert_eq!(config.extract_tenant("ac!me.example.com"), None);
+    }
+
+    #[test]
+    fn test_longest_base_domain_wins() {
+        let config = config_with_domains("example.com,eu.example.com");
+
+        assert_eq!(config.extract_tenant("acme.eu.example.com"), Some("acme".to_string()));
+        assert_eq!(config.extract_tenant("eu.example.com"), Some("eu".to_string()));
+    }
+
+    #[test]
+    fn test_multi_label_tenants() {
+        let config = config_with_domains("example.com:2");
+
+        assert_eq!(config.extract_tenant("team.org.example.com"), Some("team.org".to_string()));
+        assert_eq!(
+            config.extract_tenant("www.team.org.example.com"),
+            Some("team.org".to_string())
+        );
+        assert_eq!(config.extract_tenant("org.example.com"), None);
+    }
+
+    #[test]
+    fn test_no_base_domains_keeps_first_label() {
+        let config = TenantRoutingConfig::default();
+
+        assert_eq!(config.extract_tenant("www.acme.example.com"), Some("www".to_string()));
+        assert_eq!(config.extract_tenant("localhost"), None);
+    }
+
+    #[test]
+    fn test_host_vectors() {
+        for vector in crate::test_vectors::HOST_VECTORS {
+            let config = config_with_domains(vector.base_domains);
+
+            assert_eq!(
+                config.extract_tenant(vector.authority).as_deref(),
+                vector.tenant,
+                "authority {:?} with base domains {:?}",
+                vector.authority,
+                vector.base_domains
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_authority() {
+        assert_eq!(
+            parse_authority("Tenant1.Example.com.:8080"),
+            Some(Host::Domain("tenant1.example.com".to_string()))
+        );
+        assert_eq!(
+            parse_authority("[2001:db8::1]:8080"),
+            Some(Host::Ip("2001:db8::1".parse().unwrap()))
+        );
+        assert_eq!(
+            parse_authority("10.0.0.1:8080"),
+            Some(Host::Ip("10.0.0.1".parse().unwrap()))
+        );
+        assert_eq!(parse_authority("[2001:db8::1]:port"), None);
+        assert_eq!(parse_authority("example.com:1:2"), None);
+    }
+
+    #[test]
+    fn test_validate_base_domains() {
+        assert!(config_with_domains("example.com").validate().is_ok());
+        assert_eq!(
+            config_with_domains("example.com, example.org:0, :2").validate().unwrap_err().errors(),
+            &[
+                FieldError::new("base_domains[1].tenant_labels", ValidationError::Zero),
+                FieldError::new("base_domains[2].domain", ValidationError::Empty),
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod idna_tests {
+    use crate::idna::*;
+    use crate::tenant::*;
+
+    #[test]
+    fn test_punycode_round_trip() {
+        // Samples from RFC 3492 section 7.1, lowercased
+        let vectors = [
+            ("bücher", "xn--bcher-kva"),
+            ("münchen", "xn--mnchen-3ya"),
+            ("他们为什么不说中文", "xn--ihqwcrb4cv8a8dqg056pqjye"),
+            ("3年b組金八先生", "xn--3b-ww4c5e180e575a65lsy2b"),
+        ];
+
+        for (unicode, punycode) in vectors {
+            assert_eq!(to_ascii(unicode).as_deref(), Some(punycode));
+            assert_eq!(to_unicode(punycode), unicode);
+        }
+    }
+
+    #[test]
+    fn test_to_ascii_normalizes() {
+        // Decomposed and composed forms name the same tenant
+        assert_eq!(to_ascii("mu\u{308}nchen").as_deref(), Some("xn--mnchen-3ya"));
+        // UTS #46 maps full-width letters and case folds
+        assert_eq!(to_ascii("ＡＣＭＥ").as_deref(), Some("acme"));
+        assert_eq!(to_ascii("STRASSE.de").as_deref(), Some("strasse.de"));
+        assert_eq!(canonical_tenant_id("Mu\u{308}nchen"), Some("xn--mnchen-3ya".to_string()));
+    }
+
+    #[test]
+    fn test_to_ascii_and_back() {
+        assert_eq!(
+            to_ascii("Bücher.Example.com").as_deref(),
+            Some("xn--bcher-kva.example.com")
+        );
+        assert_eq!(to_unicode("xn--bcher-kva.example.com"), "bücher.example.com");
+        assert_eq!(to_unicode("xn--!!.example.com"), "xn--!!.example.com");
+
+        assert_eq!(to_ascii("a..example.com"), None);
+        // Non-canonical spellings would give one tenant two cache keys
+        assert_eq!(to_ascii("xn--abc-"), None);
+        assert_eq!(to_ascii("xn--bcher-kva"), Some("xn--bcher-kva".to_string()));
+    }
+
+    #[test]
+    fn test_canonical_tenant_id() {
+        assert_eq!(canonical_tenant_id(" Acme "), Some("acme".to_string()));
+        assert_eq!(canonical_tenant_id("München"), Some("xn--mnchen-3ya".to_string()));
+        assert_eq!(canonical_tenant_id("team.bücher"), Some("team.xn--bcher-kva".to_string()));
+        assert_eq!(canonical_tenant_id("tenant@"), None);
+        assert_eq!(canonical_tenant_id(""), None);
+    }
+
+    #[test]
+    fn test_object_names_use_canonical_id() {
+        assert_eq!(build_gcs_object_name("münchen"), "xn--mnchen-3ya/shard");
+        assert_eq!(build_gcs_object_name("xn--mnchen-3ya"), "xn--mnchen-3ya/shard");
+        assert_eq!(
+            build_gcs_path("bucket", "MÜNCHEN"),
+            "/bucket/xn--mnchen-3ya/shard"
+        );
+    }
+}
+
+#[cfg(test)]
+mod alias_tests {
+    use crate::alias::*;
+    use crate::cache::*;
+    use crate::mapping::MappingError;
+
+    #[test]
+    fn test_alias_object_names() {
+        let host = alias_host("PORTAL.customer.com:8443").unwrap();
+        assert_eq!(build_alias_object_name(&host), "_aliases/portal.customer.com");
+        assert_eq!(
+            build_alias_path("bucket", &alias_host("portal.bücher.de").unwrap()),
+            "/bucket/_aliases/portal.xn--bcher-kva.de"
+        );
+        assert_eq!(alias_host("[2001:db8::1]:443"), None);
+    }
+
+    #[test]
+    fn test_parse_alias_record() {
+        assert_eq!(parse_alias_record(" customer \n"), Ok("customer".to_string()));
+        assert_eq!(
+            parse_alias_record(r#"{"tenant":"Customer"}"#),
+            Ok("customer".to_string())
+        );
+        assert_eq!(
+            parse_alias_record("not a tenant"),
+            Err(MappingError::InvalidTenant("not a tenant".to_string()))
+        );
+        assert_eq!(
+            parse_alias_record(""),
+            Err(MappingError::InvalidTenant(String::new()))
+        );
+        assert!(matches!(
+            parse_alias_record("{\"tenant\":"),
+            Err(MappingError::InvalidJson(_))
+        ));
+    }
+
+    #[test]
+    fn test_alias_cache_entry() {
+        let entry = AliasCacheEntry::with_ttl(Some("customer".to_string()), 1000, 300);
+        assert!(entry.is_valid(1299));
+        assert!(!entry.is_valid(1300));
+
+        // Hosts without an alias are cached too
+        let missing = AliasCacheEntry::with_ttl(None, 1000, 300);
+        let json = serde_json::to_string(&missing).unwrap();
+        assert_eq!(serde_json::from_str::<AliasCacheEntry>(&json).unwrap(), missing);
+
+        assert_eq!(generate_alias_cache_key("portal.customer.com"), "alias:portal.customer.com");
+        assert_eq!(parse_cache_key("alias:portal.customer.com"), None);
+    }
+}
+
+#[cfg(test)]
+mod extractor_tests {
+    use crate::config::TenantRoutingConfig;
+    use crate::error::*;
+    use crate::extractor::*;
+
+    fn config_with_extractors(spec: &str) -> TenantRoutingConfig {
+        TenantRoutingConfig {
+            tenant_extractors: TenantExtractor::from_spec(spec).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    fn no_headers(_: &str) -> Option<String> {
+        None
+    }
+
+    fn tenant_header(name: &str) -> Option<String> {
+        match name {
+            "x-tenant-id" => Some("Header-Tenant".to_string()),
+            // Payload: {"tenant":"jwt-tenant","sub":"user1"}
+            "authorization" => Some(
+                "Bearer eyJhbGciOiJub25lIn0.eyJ0ZW5hbnQiOiJqd3QtdGVuYW50Iiwic3ViIjoidXNlcjEifQ.sig"
+                    .to_string(),
+            ),
+            _ => None,
+        }
+    }
+
+    fn request<'a>(
+        host: &'a str,
+        path: &'a str,
+        headers: &'a dyn Fn(&str) -> Option<String>,
+    ) -> TenantRequest<'a> {
+        TenantRequest {
+            host,
+            path,
+            headers,
+            alias: None,
+        }
+    }
+
+    #[test]
+    fn test_extractors_from_spec() {
+        assert_eq!(
+            TenantExtractor::from_spec("host, path:/tenants/, header:X-Tenant, jwt:org").unwrap(),
+            vec![
+                TenantExtractor::Host,
+                TenantExtractor::PathPrefix {
+                    prefix: "/tenants/".to_string()
+                },
+                TenantExtractor::Header {
+                    name: "x-tenant".to_string()
+                },
+                TenantExtractor::JwtClaim {
+                    claim: "org".to_string()
+                },
+            ]
+        );
+        assert!(TenantExtractor::from_spec("cookie").is_err());
+        assert!(TenantExtractor::from_spec("host:x").is_err());
+    }
+
+    #[test]
+    fn test_default_is_host_only() {
+        let config = TenantRoutingConfig::default();
+        let req = request("acme.example.com", "/t/other/", &tenant_header);
+
+        assert_eq!(config.extract_request_tenant(&req), Some("acme".to_string()));
+        assert!(config.needs_alias(&req));
+    }
+
+    #[test]
+    fn test_extractors_run_in_order() {
+        let config = config_with_extractors("header, jwt, path, host");
+        let req = request("acme.example.com", "/t/path-tenant/x", &tenant_header);
+        assert_eq!(config.extract_request_tenant(&req), Some("header-tenant".to_string()));
+        assert!(!config.needs_alias(&req));
+
+        let config = config_with_extractors("jwt, header");
+        assert_eq!(config.extract_request_tenant(&req), Some("jwt-tenant".to_string()));
+
+        let config = config_with_extractors("path, host");
+        assert_eq!(config.extract_request_tenant(&req), Some("path-tenant".to_string()));
+
+        // Nothing matches ahead of the host, so its alias is needed
+        let req = request("acme.example.com", "/orders", &no_headers);
+        assert!(config.needs_alias(&req));
+        assert_eq!(config.extract_request_tenant(&req), Some("acme".to_string()));
+    }
+
+    #[test]
+    fn test_host_extractor_prefers_alias() {
+        let config = TenantRoutingConfig::default();
+        let req = TenantRequest {
+            alias: Some("customer".to_string()),
+            ..request("portal.customer.com", "/", &no_headers)
+        };
+
+        assert_eq!(config.extract_request_tenant(&req), Some("customer".to_string()));
+    }
+
+    #[test]
+    fn test_shared_host_without_host_extractor() {
+        let config = config_with_extractors("path");
+
+        let req = request("api.example.com", "/t/acme/orders", &no_headers);
+        assert_eq!(config.extract_request_tenant(&req), Some("acme".to_string()));
+        assert!(!config.needs_alias(&req));
+
+        let req = request("api.example.com", "/orders", &no_headers);
+        assert_eq!(config.extract_request_tenant(&req), None);
+        assert!(!config.needs_alias(&req));
+    }
+
+    #[test]
+    fn test_invalid_inputs_are_skipped() {
+        let config = config_with_extractors("path, jwt, host");
+        let malformed = |name: &str| match name {
+            "authorization" => Some("Bearer not-a-jwt".to_string()),
+            _ => None,
+        };
+        let req = request("acme.example.com", "/t/bad!tenant/", &malformed);
+
+        assert_eq!(config.extract_request_tenant(&req), Some("acme".to_string()));
+        assert_eq!(tenant_from_jwt("Basic dXNlcjpwYXNz", "tenant"), None);
+    }
+
+    #[test]
+    fn test_validate_extractors() {
+        assert!(config_with_extractors("host, path, header, jwt").validate().is_ok());
+        assert_eq!(
+            config_with_extractors("").validate().unwrap_err().errors(),
+            &[FieldError::new("tenant_extractors", ValidationError::Empty)]
+        );
+        assert_eq!(
+            config_with_extractors("host, path:t, header:, jwt:").validate().unwrap_err().errors(),
+            &[
+                FieldError::new(
+                    "tenant_extractors[1].prefix",
+                    ValidationError::InvalidPathPrefix("t".to_string())
+                ),
+                FieldError::new("tenant_extractors[2].name", ValidationError::Empty),
+                FieldError::new("tenant_extractors[3].claim", ValidationError::Empty),
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod loader_tests {
+    use crate::config::{BackendErrorPolicy, PrewarmMode};
+    use crate::error::*;
+    use crate::extractor::TenantExtractor;
+    use crate::loader::*;
+
+    fn env<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| {
+            vars.iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, v)| v.to_string())
+        }
+    }
+
+    fn layer_origin(result: Result<ConfigLoader, LoadError>) -> String {
+        match result {
+            Err(LoadError::Layer { origin, .. }) => origin,
+            other => panic!("expected a layer error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = ConfigLoader::new().load().unwrap();
+        assert_eq!(config.gcs_bucket, "tenant-shard-mapping");
+        assert_eq!(config.cache_ttl_seconds, 300);
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.tenant_extractors, vec![TenantExtractor::Host]);
+    }
+
+    #[test]
+    fn test_later_layers_win() {
+        let config = ConfigLoader::new()
+            .json("file", r#"{"gcs_bucket": "from-file", "default_shard": "shard2", "cache_ttl_seconds": 60}"#)
+            .unwrap()
+            .env(env(&[("GCS_BUCKET", "from-env"), ("CACHE_TTL", "120")]))
+            .unwrap()
+            .json("plugin configuration", r#"{"cache_ttl_seconds": 30}"#)
+            .unwrap()
+            .load()
+            .unwrap();
+
+        assert_eq!(config.gcs_bucket, "from-env");
+        assert_eq!(config.default_shard, "shard2");
+        assert_eq!(config.cache_ttl_seconds, 30);
+    }
+
+    #[test]
+    fn test_env_specs() {
+        let config = ConfigLoader::new()
+            .env(env(&[
+                ("SHARDS", "shard1,shard2:2"),
+                ("FALLBACK_SHARDS", "shard1, shard2"),
+                ("BASE_DOMAINS", "example.com"),
+                ("TENANT_EXTRACTORS", "header,host"),
+                ("PORT", "9090"),
+                ("BACKEND_ERROR_POLICY", "fail_closed"),
+                ("PREWARM", "tenants"),
+                ("PREWARM_TENANTS", "acme, globex"),
+            ]))
+            .unwrap()
+            .load()
+            .unwrap();
+
+        assert_eq!(config.shards.capacity_weight("shard2"), 2);
+        assert_eq!(config.fallback_shards, vec!["shard1", "shard2"]);
+        assert_eq!(config.base_domains[0].domain, "example.com");
+        assert_eq!(config.tenant_extractors.len(), 2);
+        assert_eq!(config.port, 9090);
+        assert_eq!(config.backend_error_policy, BackendErrorPolicy::FailClosed);
+        assert_eq!(config.prewarm, PrewarmMode::Tenants);
+        assert_eq!(config.prewarm_tenants, vec!["acme", "globex"]);
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        let result = ConfigLoader::new().json("plugin configuration", r#"{"cache_ttl": 60}"#);
+        assert_eq!(layer_origin(result), "plugin configuration");
+
+        let result = ConfigLoader::new().json("file", r#"{"shards": [{"name": "shard1", "weight": 2}]}"#);
+        assert_eq!(layer_origin(result), "file");
+
+        let result = ConfigLoader::new()
+            .json("file", r#"{"tenant_extractors": [{"type": "header", "header": "x-tenant"}]}"#);
+        assert_eq!(layer_origin(result), "file");
+    }
+
+    #[test]
+    fn test_bad_env_values_name_the_variable() {
+        assert_eq!(layer_origin(ConfigLoader::new().env(env(&[("CACHE_TTL", "abc")]))), "CACHE_TTL");
+        assert_eq!(layer_origin(ConfigLoader::new().env(env(&[("PORT", "70000")]))), "PORT");
+        assert_eq!(layer_origin(ConfigLoader::new().env(env(&[("SHARDS", ":2")]))), "SHARDS");
+        assert_eq!(
+            layer_origin(ConfigLoader::new().env(env(&[("BACKEND_ERROR_POLICY", "retry")]))),
+            "BACKEND_ERROR_POLICY"
+        );
+    }
+
+    #[test]
+    fn test_load_validates() {
+        let result = ConfigLoader::new()
+            .json("file", r#"{"cache_ttl_seconds": 0}"#)
+            .unwrap()
+            .load();
+
+        match result {
+            Err(LoadError::Invalid(e)) => assert_eq!(
+                e.errors(),
+                &[FieldError::new("cache_ttl_seconds", ValidationError::Zero)]
+            ),
+            other => panic!("expected validation errors, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_shard_names_are_normalized() {
+        let config = ConfigLoader::new()
+            .json(
+                "file",
+                r#"{
+                    "shards": [{"name": "Shard-A"}, {"name": "SHARD-B", "failover": "Shard-A", "capacity_weight": 0}],
+                    "default_shard": "Shard-A",
+                    "fallback_shards": [" Shard-B "]
+                }"#,
+            )
+            .unwrap()
+            .load()
+            .unwrap();
+
+        assert!(config.shards.contains("shard-a"));
+        assert_eq!(config.shards.resolve("shard-b"), Ok("shard-a"));
+        assert_eq!(config.default_shard, "shard-a");
+        assert_eq!(config.fallback_shards, ["shard-b"]);
+        assert_eq!(config.parse_mapping("SHARD-A").unwrap().shard, "shard-a");
+    }
+
+    #[cfg(feature = "file")]
+    #[test]
+    fn test_toml_and_yaml() {
+        let toml = "gcs_bucket = \"toml-bucket\"\n\n[[shards]]\nname = \"shard1\"\n";
+        let config = ConfigLoader::new().toml("config.toml", toml).unwrap().load().unwrap();
+        assert_eq!(config.gcs_bucket, "toml-bucket");
+        assert!(config.shards.contains("shard1"));
+
+        let yaml = "gcs_bucket: yaml-bucket\nbase_domains:\n  - domain: example.com\n";
+        let config = ConfigLoader::new().yaml("config.yaml", yaml).unwrap().load().unwrap();
+        assert_eq!(config.gcs_bucket, "yaml-bucket");
+        assert_eq!(config.base_domains[0].domain, "example.com");
+
+        let result = ConfigLoader::new().yaml("config.yaml", "gcs_bukket: typo\n");
+        assert_eq!(layer_origin(result), "config.yaml");
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod source_tests {
+    use crate::source::*;
+
+    #[tokio::test]
+    async fn test_in_memory_source() {
+        let source = InMemorySource::from_spec("tenant1=shard2, tenant2 = shard1").unwrap();
+
+        assert_eq!(source.read_tenant_mapping("tenant1").await, Ok("shard2".to_string()));
+        assert_eq!(source.read_object("tenant2/shard").await, Ok("shard1".to_string()));
+        assert_eq!(
+            source.read_tenant_mapping("missing").await,
+            Err(SourceError::NotFound("missing/shard".to_string()))
+        );
+
+        source.insert_alias("portal.customer.com", "tenant1");
+        assert_eq!(source.read_alias("portal.customer.com").await, Ok("tenant1".to_string()));
+
+        source.set_generation(4);
+        assert_eq!(source.read_generation().await, Ok("4".to_string()));
+
+        assert!(InMemorySource::from_spec("").unwrap().is_empty());
+        assert!(InMemorySource::from_spec("tenant1").is_err());
+        assert!(InMemorySource::from_spec("=shard1").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_local_dir_source() {
+        let root = std::env::temp_dir().join(format!("tenant-routing-core-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("tenant1")).unwrap();
+        std::fs::write(root.join("tenant1/shard"), "shard2\n").unwrap();
+
+        let source = LocalDirSource::new(&root);
+        assert_eq!(source.read_tenant_mapping("tenant1").await, Ok("shard2\n".to_string()));
+        assert_eq!(
+            source.read_tenant_mapping("tenant2").await,
+            Err(SourceError::NotFound("tenant2/shard".to_string()))
+        );
+
+        // Object names must stay inside the root directory
+        assert!(matches!(
+            source.read_object("../etc/passwd").await,
+            Err(SourceError::NotFound(_))
+        ));
+        assert!(matches!(
+            source.read_object("/etc/passwd").await,
+            Err(SourceError::NotFound(_))
+        ));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    async fn check_conditional_writes(source: &dyn MappingSource) {
+        let object = "tenant1/shard";
+
+        let version = source
+            .write_object(object, "shard1", Precondition::DoesNotExist)
+            .await
+            .unwrap();
+        assert_eq!(
+            source.read_versioned(object).await,
+            Ok(VersionedObject {
+                contents: "shard1".to_string(),
+                version,
+            })
+        );
+
+        // Creating it again or writing against a stale version must fail
+        assert_eq!(
+            source.write_object(object, "shard2", Precondition::DoesNotExist).await,
+            Err(SourceError::PreconditionFailed(object.to_string()))
+        );
+        let updated = source
+            .write_object(object, "shard2", Precondition::Matches(version))
+            .await
+            .unwrap();
+        assert_ne!(updated, version);
+        assert_eq!(
+            source.write_object(object, "shard3", Precondition::Matches(version)).await,
+            Err(SourceError::PreconditionFailed(object.to_string()))
+        );
+        assert_eq!(source.read_object(object).await, Ok("shard2".to_string()));
+
+        assert_eq!(
+            source.delete_object(object, Precondition::Matches(version)).await,
+            Err(SourceError::PreconditionFailed(object.to_string()))
+        );
+        assert_eq!(source.delete_object(object, Precondition::Matches(updated)).await, Ok(()));
+        assert_eq!(
+            source.read_versioned(object).await,
+            Err(SourceError::NotFound(object.to_string()))
+        );
+        assert_eq!(
+            source.delete_object(object, Precondition::Matches(updated)).await,
+            Err(SourceError::NotFound(object.to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_conditional_writes() {
+        check_conditional_writes(&InMemorySource::new()).await;
+
+        let root = std::env::temp_dir().join(format!(
+            "tenant-routing-core-writes-{}",
+            std::process::id()
+        ));
+        check_conditional_writes(&LocalDirSource::new(&root)).await;
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_list_tenants() {
+        let source = InMemorySource::from_spec("tenant1=shard1, tenant2=shard2").unwrap();
+        source.insert_alias("portal.customer.com", "tenant1");
+        source.set_generation(3);
+        let mut tenants = source.list_tenants().await.unwrap();
+        tenants.sort();
+        assert_eq!(tenants, vec!["tenant1", "tenant2"]);
+
+        let root = std::env::temp_dir().join(format!(
+            "tenant-routing-core-list-{}",
+            std::process::id()
+        ));
+        for dir in ["tenant1", "Tenant2", "_meta", "empty"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for object in ["tenant1/shard", "Tenant2/shard", "_meta/shard"] {
+            std::fs::write(root.join(object), "shard1").unwrap();
+        }
+        assert_eq!(LocalDirSource::new(&root).list_tenants().await, Ok(vec!["tenant1".to_string()]));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_probe() {
+        // A missing canary is fine, an unreadable store is not
+        assert_eq!(InMemorySource::new().probe().await, Ok(()));
+        assert!(matches!(
+            LocalDirSource::new("/dev/null").probe().await,
+            Err(SourceError::Backend(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_source_config_build() {
+        let config = SourceConfig::Memory {
+            spec: "tenant1=shard3".to_string(),
+        };
+        let source = config.build().await.unwrap();
+        assert_eq!(source.read_tenant_mapping("tenant1").await, Ok("shard3".to_string()));
+    }
+}
+#[cfg(all(test, feature = "std"))]
+mod health_tests {
+    use crate::health::*;
+    use crate::source::*;
+
+    struct DownSource;
+
+    #[async_trait::async_trait]
+    impl MappingSource for DownSource {
+        fn describe(&self) -> String {
+            "down".to_string()
+        }
+
+        async fn read_object(&self, _object: &str) -> Result<String, SourceError> {
+            Err(SourceError::Backend("unavailable".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_report_for_source() {
+        // A missing canary still shows the store answers
+        let report = ReadinessReport::for_source(&InMemorySource::new()).await;
+        assert!(report.ready);
+        assert!(report.components[MAPPING_STORE].latency_ms.is_some());
+        assert_eq!(report.failures().count(), 0);
+
+        let mut report = ReadinessReport::for_source(&DownSource).await;
+        report.add("cache_prewarm", ComponentStatus::ready());
+        assert!(!report.ready);
+        assert_eq!(
+            report.failures().collect::<Vec<_>>(),
+            vec![(MAPPING_STORE, "backend error: unavailable")]
+        );
+    }
+}
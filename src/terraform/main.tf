@@ -13,6 +13,29 @@ provider "google" {
   region  = var.region
 }
 
+locals {
+  # The extractor spec as the routers' JSON config
+  tenant_extractor_config = [
+    for entry in split(",", var.tenant_extractors) : {
+      host   = { type = "host" }
+      path   = { type = "path_prefix", prefix = try(trimspace(split(":", entry)[1]), "/t/") }
+      header = { type = "header", name = lower(try(trimspace(split(":", entry)[1]), "x-tenant-id")) }
+      jwt    = { type = "jwt_claim", claim = try(trimspace(split(":", entry)[1]), "tenant") }
+    }[trimspace(split(":", entry)[0])]
+    if trimspace(entry) != ""
+  ]
+
+  tenant_headers = distinct([
+    for extractor in local.tenant_extractor_config : extractor.name if extractor.type == "header"
+  ])
+
+  # Headers the Lua filter forwards to /lookup for the header and JWT extractors
+  tenant_lookup_headers = concat(
+    local.tenant_headers,
+    anytrue([for extractor in local.tenant_extractor_config : extractor.type == "jwt_claim"]) ? ["authorization"] : []
+  )
+}
+
 # Health checks module
 module "health_checks" {
   source = "./modules/health-checks"
@@ -50,7 +73,14 @@ module "envoy" {
   gcs_bucket_name        = module.gcs.bucket_name
   service_account_email  = google_service_account.envoy.email
   use_lua_filter         = var.use_lua_filter
+  split_key_header       = var.split_key_header
   project_id             = var.project_id
+
+  # Tenant extractors, with their headers stripped from client requests
+  tenant_extractors       = var.tenant_extractors
+  tenant_extractor_config = local.tenant_extractor_config
+  tenant_lookup_headers   = local.tenant_lookup_headers
+  stripped_tenant_headers = var.strip_tenant_headers ? local.tenant_headers : []
 }
 
 # Main load balancer module (single-region)
@@ -125,6 +155,13 @@ module "envoy_global" {
 
   # Use Lua filter by default
   use_lua_filter   = var.use_lua_filter
+  split_key_header = var.split_key_header
+
+  # Tenant extractors, with their headers stripped from client requests
+  tenant_extractors       = var.tenant_extractors
+  tenant_extractor_config = local.tenant_extractor_config
+  tenant_lookup_headers   = local.tenant_lookup_headers
+  stripped_tenant_headers = var.strip_tenant_headers ? local.tenant_headers : []
 
   # Enable security features
   enable_cdn         = true
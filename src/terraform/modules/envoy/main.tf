@@ -5,11 +5,19 @@ locals {
     shard_backends      = var.shard_backends
     gcs_bucket_name     = var.gcs_bucket_name
     default_shard       = var.shard_names[0]  # First shard as default
+    split_key_header    = var.split_key_header
+    tenant_extractor_config = var.tenant_extractor_config
+    tenant_lookup_headers   = var.tenant_lookup_headers
+    stripped_tenant_headers = var.stripped_tenant_headers
   }) : templatefile("${path.module}/templates/envoy-wasm.yaml.tpl", {
     shard_names         = var.shard_names
     shard_backends      = var.shard_backends
     gcs_bucket_name     = var.gcs_bucket_name
     default_shard       = var.shard_names[0]  # First shard as default
+    split_key_header    = var.split_key_header
+    tenant_extractor_config = var.tenant_extractor_config
+    tenant_lookup_headers   = var.tenant_lookup_headers
+    stripped_tenant_headers = var.stripped_tenant_headers
   })
 }
 
@@ -38,6 +46,8 @@ resource "google_compute_instance" "envoy_router" {
       envoy_config   = local.envoy_config
       gcs_bucket_name = var.gcs_bucket_name
       default_shard   = var.shard_names[0]
+      split_key_header = var.split_key_header
+      tenant_extractors = var.tenant_extractors
       shard_names     = var.shard_names
       shard_backends  = var.shard_backends
       project_id      = var.project_id
@@ -21,6 +21,9 @@ docker run -d \
   --log-opt gcp-project=${project_id} \
   -e GCS_BUCKET=${gcs_bucket_name} \
   -e DEFAULT_SHARD=${default_shard} \
+  -e SPLIT_KEY_HEADER=${split_key_header} \
+  -e TENANT_EXTRACTORS=${tenant_extractors} \
+  -e SHARDS=${join(",", shard_names)} \
   -e CACHE_TTL=300 \
   -e PORT=8080 \
   -e RUST_LOG=info \
@@ -28,7 +31,7 @@ docker run -d \
 
 # Wait for tenant lookup service to be ready
 for i in {1..30}; do
-    if curl -s http://localhost:8080/health > /dev/null; then
+    if curl -sf http://localhost:8080/readyz > /dev/null; then
         echo "Tenant lookup service is ready"
         break
     fi
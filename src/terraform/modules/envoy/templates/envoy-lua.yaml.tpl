@@ -44,13 +44,34 @@ static_resources:
                 route:
                   cluster: ${default_shard}
           http_filters:
+%{ if length(stripped_tenant_headers) > 0 ~}
+          # Clients must not pick their tenant through the header extractor
+          - name: envoy.filters.http.header_mutation
+            typed_config:
+              "@type": type.googleapis.com/envoy.extensions.filters.http.header_mutation.v3.HeaderMutation
+              mutations:
+                request_mutations:
+%{ for name in stripped_tenant_headers ~}
+                - remove: "${name}"
+%{ endfor ~}
+%{ endif ~}
           # Lua filter for tenant lookup
           - name: envoy.filters.http.lua
             typed_config:
               "@type": type.googleapis.com/envoy.extensions.filters.http.lua.v3.Lua
               default_source_code:
                 inline_string: |
+                  local function url_encode(value)
+                    local encoded = string.gsub(value, "[^%w%-%._~]", function(c)
+                      return string.format("%%%02X", string.byte(c))
+                    end)
+                    return encoded
+                  end
+
                   function envoy_on_request(request_handle)
+                    -- Only the lookup may set the phase, so drop any the client sent
+                    request_handle:headers():remove("x-tenant-migration-phase")
+
                     -- Get the host header
                     local host = request_handle:headers():get(":authority")
                     if not host then
@@ -58,23 +79,71 @@ static_resources:
                     end
                     
                     if host then
+                      local method = request_handle:headers():get(":method") or "GET"
+                      -- Encoded so IPv6 literals like [2001:db8::1]:8080 survive the query string
+                      local path = "/lookup?host=" .. url_encode(host) .. "&method=" .. method
+
+                      -- Session key keeps a user on one shard when the tenant's traffic is split
+                      local key = request_handle:headers():get("${split_key_header}")
+                      if not key then
+                        local xff = request_handle:headers():get("x-forwarded-for")
+                        if xff then
+                          key = string.match(xff, "^%s*([^,%s]+)")
+                        end
+                      end
+                      if key then
+                        path = path .. "&key=" .. url_encode(key)
+                      end
+
+                      -- Inputs for the path, header and JWT tenant extractors
+                      local request_path = request_handle:headers():get(":path")
+                      if request_path then
+                        path = path .. "&path=" .. url_encode(request_path)
+                      end
+                      local lookup_headers = {
+                        [":method"] = "GET",
+                        [":path"] = path,
+                        [":authority"] = "tenant-lookup"
+                      }
+                      for _, name in ipairs({${join(", ", [for name in tenant_lookup_headers : jsonencode(name)])}}) do
+                        local value = request_handle:headers():get(name)
+                        if value then
+                          lookup_headers[name] = value
+                        end
+                      end
+
                       -- Call the tenant lookup service
                       local headers, body = request_handle:httpCall(
                         "tenant_lookup_cluster",
-                        {
-                          [":method"] = "GET",
-                          [":path"] = "/lookup?host=" .. host,
-                          [":authority"] = "tenant-lookup"
-                        },
+                        lookup_headers,
                         "",
                         5000
                       )
                       
-                      if headers and headers[":status"] == "200" and body then
+                      if headers and headers[":status"] == "503" and body
+                        and string.find(body, '"outcome":"backend_error"', 1, true) then
+                        -- Mapping store is down and the lookup service fails closed
+                        request_handle:respond(
+                          {[":status"] = "503"},
+                          "Tenant routing is unavailable, retry later"
+                        )
+                      elseif headers and headers[":status"] == "503" and body then
+                        -- Tenant is frozen for migration, hold the request back
+                        local phase = string.match(body, '"migration_phase":"([^"]+)"') or "read_only"
+                        request_handle:respond(
+                          {
+                            [":status"] = "503",
+                            ["retry-after"] = headers["retry-after"] or "30",
+                            ["x-tenant-migration-phase"] = phase
+                          },
+                          "Tenant is being migrated, retry later"
+                        )
+                      elseif headers and headers[":status"] == "200" and body then
                         -- Parse JSON response manually (Envoy Lua doesn't have cjson)
-                        -- Expected format: {"shard":"shard1","tenant":"tenant1"}
+                        -- Expected format: {"shard":"shard1","tenant":"tenant1","migration_phase":"draining","outcome":"found"}
                         local shard = string.match(body, '"shard":"([^"]+)"')
                         local tenant = string.match(body, '"tenant":"([^"]+)"')
+                        local phase = string.match(body, '"migration_phase":"([^"]+)"')
                         
                         if shard then
                           -- Set the shard header
@@ -83,6 +152,10 @@ static_resources:
                           if tenant then
                             request_handle:headers():add("x-tenant-name", tenant)
                           end
+
+                          if phase then
+                            request_handle:headers():replace("x-tenant-migration-phase", phase)
+                          end
                           
                           request_handle:logInfo("Tenant lookup: " .. host .. " -> " .. shard)
                         else
@@ -23,6 +23,7 @@ docker run -d \
   --log-driver=gcplogs \
   --log-opt gcp-log-cmd=true \
   --log-opt gcp-project=${project_id} \
+  -e GCS_BUCKET=${gcs_bucket_name} \
   -e PORT=8080 \
   -e RUST_LOG=info \
   gcr.io/${project_id}/gcs-proxy:latest
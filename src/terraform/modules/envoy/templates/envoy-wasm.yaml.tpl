@@ -44,6 +44,17 @@ static_resources:
                 route:
                   cluster: ${default_shard}
           http_filters:
+%{ if length(stripped_tenant_headers) > 0 ~}
+          # Clients must not pick their tenant through the header extractor
+          - name: envoy.filters.http.header_mutation
+            typed_config:
+              "@type": type.googleapis.com/envoy.extensions.filters.http.header_mutation.v3.HeaderMutation
+              mutations:
+                request_mutations:
+%{ for name in stripped_tenant_headers ~}
+                - remove: "${name}"
+%{ endfor ~}
+%{ endif ~}
           - name: envoy.filters.http.wasm
             typed_config:
               "@type": type.googleapis.com/envoy.extensions.filters.http.wasm.v3.Wasm
@@ -61,7 +72,10 @@ static_resources:
                     {
                       "gcs_bucket": "${gcs_bucket_name}",
                       "cache_ttl_seconds": 300,
-                      "default_shard": "${default_shard}"
+                      "default_shard": "${default_shard}",
+                      "split_key_header": "${split_key_header}",
+                      "tenant_extractors": ${jsonencode(tenant_extractor_config)},
+                      "shards": ${jsonencode([for s in shard_names : { name = s }])}
                     }
           - name: envoy.filters.http.router
             typed_config:
@@ -53,6 +53,36 @@ variable "use_lua_filter" {
   default     = false
 }
 
+variable "split_key_header" {
+  description = "Request header that keeps a user on one shard when a tenant's traffic is split"
+  type        = string
+  default     = "x-session-id"
+}
+
+variable "tenant_extractors" {
+  description = "Ordered tenant extractors for the lookup service, e.g. \"header:x-tenant-id,host\""
+  type        = string
+  default     = "host"
+}
+
+variable "tenant_extractor_config" {
+  description = "The tenant extractors as the WASM filter's JSON config"
+  type        = any
+  default     = [{ type = "host" }]
+}
+
+variable "tenant_lookup_headers" {
+  description = "Request headers the Lua filter forwards to the lookup service for the header and JWT extractors"
+  type        = list(string)
+  default     = []
+}
+
+variable "stripped_tenant_headers" {
+  description = "Request headers removed before the tenant lookup so clients cannot choose their tenant"
+  type        = list(string)
+  default     = []
+}
+
 variable "project_id" {
   description = "GCP Project ID"
   type        = string
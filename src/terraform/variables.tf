@@ -64,6 +64,24 @@ variable "bigquery_dataset_id" {
   default     = "envoy_logs"
 }
 
+variable "split_key_header" {
+  description = "Request header that keeps a user on one shard when a tenant's traffic is split"
+  type        = string
+  default     = "x-session-id"
+}
+
+variable "tenant_extractors" {
+  description = "Ordered tenant extractors, e.g. \"header:x-tenant-id,path:/t/,host\""
+  type        = string
+  default     = "host"
+}
+
+variable "strip_tenant_headers" {
+  description = "Remove client-supplied header extractor headers at Envoy. Disable only behind a proxy that strips and sets them itself"
+  type        = bool
+  default     = true
+}
+
 variable "use_global_deployment" {
   description = "Deploy Envoy globally across multiple regions"
   type        = bool
@@ -3,31 +3,63 @@ use proxy_wasm::traits::*;
 use proxy_wasm::types::*;
 use std::time::Duration;
 use tenant_routing_core::{
-    cache::{generate_cache_key, CacheEntry},
-    config::TenantRoutingConfig,
-    tenant::{build_gcs_path, extract_tenant_from_host, normalize_shard_name},
+    alias::{alias_host, build_alias_path, parse_alias_record},
+    cache::{
+        build_generation_path, generate_alias_cache_key, generate_cache_key,
+        generate_negative_cache_key, parse_generation_record,
+        AliasCacheEntry, CacheEntry, Freshness, GenerationCacheEntry, NegativeCacheEntry,
+        GENERATION_CACHE_KEY,
+    },
+    config::{BackendErrorPolicy, TenantRoutingConfig},
+    error::LoadError,
+    extractor::TenantRequest,
+    loader::{load_error_messages, ConfigLoader},
+    mapping::{LookupOutcome, MappingError, MappingRecord},
+    migration::{is_write_method, RequestContext, RouteDecision, MIGRATION_PHASE_HEADER},
+    tenant::build_gcs_path,
 };
 
-#[no_mangle]
+// Host test binaries bring their own `_start`
+#[cfg_attr(not(test), unsafe(no_mangle))]
 pub fn _start() {
     proxy_wasm::set_log_level(LogLevel::Info);
     proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> { Box::new(TenantRouterRoot::new()) });
 }
 
+/// Timeout for mapping store lookups, in seconds.
+const LOOKUP_TIMEOUT_SECONDS: u64 = 5;
+
 struct TenantRouterRoot {
     config: TenantRoutingConfig,
+    unknown_shard_metric: Option<u32>,
 }
 
 struct TenantRouter {
     config: TenantRoutingConfig,
+    unknown_shard_metric: Option<u32>,
     pending_request: Option<u32>,
+    /// Host whose alias lookup is in flight
+    pending_alias: Option<String>,
     pending_tenant: String,
+    /// Token and tenant of a background refresh in flight
+    refresh_request: Option<(u32, String)>,
+    /// Expired mapping to route with if the pending lookup fails
+    stale_entry: Option<CacheEntry>,
+    /// Cache generation this request's shared data entries must carry
+    generation: u64,
+    /// Token of a background cache generation check in flight
+    generation_request: Option<u32>,
+    /// Tenant resolved from the host (via alias or labels), if any
+    tenant_name: Option<String>,
+    split_key: String,
+    is_write: bool,
 }
 
 impl TenantRouterRoot {
     fn new() -> Self {
         Self {
             config: TenantRoutingConfig::default(),
+            unknown_shard_metric: None,
         }
     }
 }
@@ -36,34 +68,40 @@ impl Context for TenantRouterRoot {}
 
 impl RootContext for TenantRouterRoot {
     fn on_configure(&mut self, _config_size: usize) -> bool {
-        if let Some(config_bytes) = self.get_plugin_configuration() {
-            if let Ok(config_str) = std::str::from_utf8(&config_bytes) {
-                if let Ok(config_json) = serde_json::from_str::<serde_json::Value>(config_str) {
-                    if let Some(bucket) = config_json.get("gcs_bucket").and_then(|v| v.as_str()) {
-                        self.config.gcs_bucket = bucket.to_string();
-                    }
-
-                    if let Some(ttl) = config_json
-                        .get("cache_ttl_seconds")
-                        .and_then(|v| v.as_u64())
-                    {
-                        self.config.cache_ttl_seconds = ttl;
-                    }
-
-                    if let Some(shard) = config_json.get("default_shard").and_then(|v| v.as_str()) {
-                        self.config.default_shard = shard.to_string();
-                    }
+        // Refuse to start rather than route with a broken configuration
+        self.config = match load_plugin_config(self.get_plugin_configuration().as_deref()) {
+            Ok(config) => config,
+            Err(e) => {
+                for message in load_error_messages(&e) {
+                    error!("Invalid configuration: {}", message);
                 }
+                return false;
             }
-        }
+        };
+
+        self.unknown_shard_metric = proxy_wasm::hostcalls::define_metric(
+            MetricType::Counter,
+            "tenant_router.unknown_shard_mappings",
+        )
+        .ok();
+
         true
     }
 
     fn create_http_context(&self, _context_id: u32) -> Option<Box<dyn HttpContext>> {
         Some(Box::new(TenantRouter {
             config: self.config.clone(),
+            unknown_shard_metric: self.unknown_shard_metric,
             pending_request: None,
+            pending_alias: None,
             pending_tenant: String::new(),
+            refresh_request: None,
+            stale_entry: None,
+            generation: 0,
+            generation_request: None,
+            tenant_name: None,
+            split_key: String::new(),
+            is_write: false,
         }))
     }
 
@@ -75,52 +113,79 @@ impl RootContext for TenantRouterRoot {
 impl Context for TenantRouter {
     fn on_http_call_response(
         &mut self,
-        _token_id: u32,
+        token_id: u32,
         _num_headers: usize,
         body_size: usize,
         _num_trailers: usize,
     ) {
         info!("HTTP call response received, body_size: {}", body_size);
 
-        if let Some(body) = self.get_http_call_response_body(0, body_size) {
-            if let Ok(shard) = std::str::from_utf8(&body) {
-                let shard = shard.trim();
-                info!("Response body parsed as shard: '{}'", shard);
-
-                // Determine which tenant we're caching for
-                let cache_tenant = self.pending_tenant.clone();
-
-                if !cache_tenant.is_empty() {
-                    // Store in cache
-                    let current_time = self
-                        .get_current_time()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs();
-
-                    let cache_entry = CacheEntry::with_ttl(
-                        normalize_shard_name(shard),
-                        current_time,
-                        self.config.cache_ttl_seconds,
-                    );
+        if self.generation_request == Some(token_id) {
+            self.generation_request = None;
+            self.handle_generation_response(body_size);
+            return;
+        }
 
-                    if let Ok(serialized) = serde_json::to_vec(&cache_entry) {
-                        if let Err(e) = self.set_shared_data(
-                            &generate_cache_key(&cache_tenant),
-                            Some(&serialized),
-                            None,
-                        ) {
-                            warn!("Failed to cache tenant {}: {:?}", cache_tenant, e);
-                        }
-                    }
+        if self
+            .refresh_request
+            .as_ref()
+            .is_some_and(|(token, _)| *token == token_id)
+        {
+            // Background refresh; the request was already routed
+            if let Some((_, tenant)) = self.refresh_request.take()
+                && let Ok(record) = self.read_mapping_response(&tenant, body_size)
+            {
+                self.cache_mapping(&tenant, record);
+            }
+            return;
+        }
 
-                    info!("Cached tenant {} -> {}", cache_tenant, shard);
-                }
+        if let Some(host) = self.pending_alias.take() {
+            let alias = self.handle_alias_response(&host, body_size);
+            let authority = self
+                .get_http_request_header(":authority")
+                .unwrap_or_default();
 
-                // Continue the request with the shard
-                self.set_shard_headers(shard);
+            if self.route_tenant(&authority, alias) == Action::Continue {
                 self.resume_http_request();
+            }
+            return;
+        }
+
+        let tenant = self.pending_tenant.clone();
+        let outcome = match self.read_mapping_response(&tenant, body_size) {
+            Ok(record) => {
+                info!("Response body parsed as shard: '{}'", record.shard);
+                self.stale_entry = None;
+
+                let cache_entry = self.cache_mapping(&tenant, record);
 
+                // Continue the request with the shard
+                if self.apply_route(&tenant, &cache_entry) {
+                    self.resume_http_request();
+                }
+
+                return;
+            }
+            Err(outcome) => outcome,
+        };
+
+        if outcome == LookupOutcome::BackendError {
+            // Keep serving the last known shard while the mapping store is failing
+            if let Some(entry) = self.stale_entry.take() {
+                warn!(
+                    "Serving stale shard {} for tenant {}",
+                    entry.shard, tenant
+                );
+
+                if self.apply_route(&tenant, &entry) {
+                    self.resume_http_request();
+                }
+                return;
+            }
+
+            if self.config.backend_error_policy == BackendErrorPolicy::FailClosed {
+                self.reject_unavailable(&tenant);
                 return;
             }
         }
@@ -131,15 +196,26 @@ impl Context for TenantRouter {
             body_size, self.pending_tenant
         );
 
-        if self.pending_tenant != "default" {
+        if self.pending_tenant != "default" && self.config.uses_hash_fallback() {
+            // Spread unmapped tenants over the fallback shards
+            let shard = self.config.fallback_shard(&self.pending_tenant).to_string();
+            warn!(
+                "No mapping for tenant {}, using fallback shard {}",
+                self.pending_tenant, shard
+            );
+
+            self.set_shard_headers(&shard);
+            self.resume_http_request();
+        } else if self.pending_tenant != "default" {
             warn!("Failed to get shard from GCS, trying /default/shard");
             self.pending_tenant = "default".to_string();
 
             match self.lookup_tenant_shard("default") {
-                Ok(Some(shard)) => {
+                Ok(Some(entry)) => {
                     // Found in cache
-                    self.set_shard_headers(&shard);
-                    self.resume_http_request();
+                    if self.apply_route("default", &entry) {
+                        self.resume_http_request();
+                    }
                 }
                 Ok(None) => {
                     // Failed to dispatch, use configured default
@@ -166,12 +242,81 @@ impl Context for TenantRouter {
 
 impl HttpContext for TenantRouter {
     fn on_http_request_headers(&mut self, _num_headers: usize, _end_of_stream: bool) -> Action {
-        // Extract tenant from hostname
+        self.generation = self.cache_generation();
+
         let authority = self
             .get_http_request_header(":authority")
             .unwrap_or_default();
 
-        let tenant = extract_tenant_from_host(&authority).unwrap_or_else(|| "default".to_string());
+        self.split_key = self.get_split_key();
+        self.is_write = is_write_method(
+            &self
+                .get_http_request_header(":method")
+                .unwrap_or_default(),
+        );
+
+        // Vanity domains take precedence over the host's labels
+        let needs_alias =
+            self.with_tenant_request(&authority, None, |request| self.config.needs_alias(request));
+        let alias = match alias_host(&authority).filter(|_| needs_alias) {
+            Some(host) => match self.lookup_alias(&host) {
+                Ok(Some(entry)) => entry.tenant,
+                Ok(None) => None,
+                Err(action) => {
+                    // Paused for lookup
+                    self.pending_alias = Some(host);
+                    return action;
+                }
+            },
+            None => None,
+        };
+
+        self.route_tenant(&authority, alias)
+    }
+}
+
+/// Built-in defaults overlaid with the plugin configuration, if any.
+fn load_plugin_config(config_bytes: Option<&[u8]>) -> Result<TenantRoutingConfig, LoadError> {
+    let mut loader = ConfigLoader::new();
+    if let Some(config_bytes) = config_bytes {
+        let raw = std::str::from_utf8(config_bytes).map_err(|e| LoadError::Layer {
+            origin: "plugin configuration".to_string(),
+            message: e.to_string(),
+        })?;
+        loader = loader.json("plugin configuration", raw)?;
+    }
+    loader.load()
+}
+
+impl TenantRouter {
+    /// Run `f` on the inputs for the configured tenant extractors.
+    fn with_tenant_request<R>(
+        &self,
+        authority: &str,
+        alias: Option<String>,
+        f: impl FnOnce(&TenantRequest) -> R,
+    ) -> R {
+        let path = self.get_http_request_header(":path").unwrap_or_default();
+        let headers = |name: &str| self.get_http_request_header(name);
+
+        f(&TenantRequest {
+            host: authority,
+            path: &path,
+            headers: &headers,
+            alias,
+        })
+    }
+
+    /// Route the request for the tenant found by the configured extractors
+    /// (`alias` standing in for the host's labels), else the default tenant.
+    fn route_tenant(&mut self, authority: &str, alias: Option<String>) -> Action {
+        self.tenant_name = self.with_tenant_request(authority, alias, |request| {
+            self.config.extract_request_tenant(request)
+        });
+        let tenant = self
+            .tenant_name
+            .clone()
+            .unwrap_or_else(|| "default".to_string());
 
         info!(
             "Processing request for host: '{}', extracted tenant: '{}'",
@@ -183,20 +328,21 @@ impl HttpContext for TenantRouter {
 
         // Try to look up the tenant's shard
         match self.lookup_tenant_shard(&tenant) {
-            Ok(Some(shard)) => {
+            Ok(Some(entry)) => {
                 // Found in cache
-                self.set_shard_headers(&shard);
+                self.route_action(&tenant, &entry)
+            }
+            Ok(None) if tenant != "default" && self.config.uses_hash_fallback() => {
+                // No mapping or failed to dispatch, use the tenant's fallback shard
+                self.set_shard_headers(self.config.fallback_shard(&tenant));
                 Action::Continue
             }
             Ok(None) => {
-                // Failed to dispatch request, try default
+                // No mapping or failed to dispatch, try default
                 self.pending_tenant = "default".to_string();
 
                 match self.lookup_tenant_shard("default") {
-                    Ok(Some(shard)) => {
-                        self.set_shard_headers(&shard);
-                        Action::Continue
-                    }
+                    Ok(Some(entry)) => self.route_action("default", &entry),
                     Ok(None) => {
                         // Failed to dispatch default lookup too
                         warn!(
@@ -213,20 +359,87 @@ impl HttpContext for TenantRouter {
             Err(action) => action, // Paused for lookup
         }
     }
-}
 
-impl TenantRouter {
+    fn now(&self) -> u64 {
+        self.get_current_time()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    /// Set the routing headers for `entry`, or reply 503 if the tenant is
+    /// frozen for migration. Returns false when a local reply was sent.
+    fn apply_route(&self, tenant: &str, entry: &CacheEntry) -> bool {
+        let ctx = RequestContext {
+            tenant,
+            key: &self.split_key,
+            is_write: self.is_write,
+            now: self.now(),
+        };
+
+        match entry.route(&ctx) {
+            RouteDecision::Forward { shard, phase } => {
+                self.set_shard_headers(self.config.shards.resolve(shard).unwrap_or(shard));
+                if let Some(phase) = phase {
+                    self.set_http_request_header(MIGRATION_PHASE_HEADER, Some(phase.as_str()));
+                }
+                true
+            }
+            RouteDecision::Unavailable {
+                phase,
+                retry_after_seconds,
+            } => {
+                info!(
+                    "Tenant {} is {}, rejecting write request",
+                    tenant,
+                    phase.as_str()
+                );
+
+                let retry_after = retry_after_seconds.to_string();
+                self.send_http_response(
+                    503,
+                    vec![
+                        ("retry-after", &retry_after),
+                        (MIGRATION_PHASE_HEADER, phase.as_str()),
+                    ],
+                    Some(b"Tenant is being migrated, retry later"),
+                );
+                false
+            }
+        }
+    }
+
+    fn route_action(&self, tenant: &str, entry: &CacheEntry) -> Action {
+        if self.apply_route(tenant, entry) {
+            Action::Continue
+        } else {
+            Action::Pause
+        }
+    }
+
+    /// Set the routing headers for `shard`, clearing any migration phase
+    /// header sent by the client.
     fn set_shard_headers(&self, shard: &str) {
         info!("Setting x-tenant-shard header to: '{}'", shard);
         self.set_http_request_header("x-tenant-shard", Some(shard));
+        self.set_http_request_header(MIGRATION_PHASE_HEADER, None);
 
-        if let Some(tenant) = self.get_http_request_header(":authority") {
-            if let Some(dot_pos) = tenant.find('.') {
-                self.set_http_request_header("x-tenant-name", Some(&tenant[..dot_pos]));
-            }
+        if let Some(tenant) = &self.tenant_name {
+            self.set_http_request_header("x-tenant-name", Some(tenant));
         }
     }
 
+    /// Key used to keep a user on one shard when the tenant's traffic is
+    /// split: the configured session header, else the client address.
+    fn get_split_key(&self) -> String {
+        self.get_http_request_header(&self.config.split_key_header)
+            .or_else(|| {
+                self.get_http_request_header("x-forwarded-for")
+                    .and_then(|xff| xff.split(',').next().map(|ip| ip.trim().to_string()))
+            })
+            .unwrap_or_default()
+    }
+
     fn dispatch_gcs_lookup(&mut self, path: &str) -> Result<u32, Status> {
         // Add /gcs prefix for the GCS proxy route
         let proxy_path = format!("/gcs{}", path);
@@ -239,32 +452,340 @@ impl TenantRouter {
 
         info!("Dispatching GCS lookup for path: '{}'", proxy_path);
 
-        self.dispatch_http_call("gcs_proxy", headers, None, vec![], Duration::from_secs(5))
+        self.dispatch_http_call(
+            "gcs_proxy",
+            headers,
+            None,
+            vec![],
+            Duration::from_secs(LOOKUP_TIMEOUT_SECONDS),
+        )
     }
 
-    fn lookup_tenant_shard(&mut self, tenant: &str) -> Result<Option<String>, Action> {
-        // Check cache first
-        let cache_key = generate_cache_key(tenant);
-
-        if let (Some(cached_data), _) = self.get_shared_data(&cache_key) {
-            if let Ok(cache_str) = std::str::from_utf8(&cached_data) {
-                if let Ok(cache_entry) = serde_json::from_str::<CacheEntry>(cache_str) {
-                    let now = self
-                        .get_current_time()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs();
-
-                    if cache_entry.is_valid(now) {
-                        info!(
-                            "Using cached shard for tenant {}: {}",
-                            tenant, cache_entry.shard
-                        );
+    /// Current cache generation. Once the cached value is due for a check,
+    /// it is claimed with compare-and-swap so only one request across all
+    /// workers fetches it in the background; requests keep using the
+    /// cached generation meanwhile.
+    fn cache_generation(&mut self) -> u64 {
+        if self.config.generation_check_seconds == 0 {
+            return 0;
+        }
 
-                        return Ok(Some(cache_entry.shard));
+        let now = self.now();
+        let (cached_data, cas) = self.get_shared_data(GENERATION_CACHE_KEY);
+        let cached = cached_data
+            .and_then(|data| serde_json::from_slice::<GenerationCacheEntry>(&data).ok());
+        let generation = cached.as_ref().map_or(0, |entry| entry.generation);
+        if cached.is_some_and(|entry| entry.is_valid(now)) {
+            return generation;
+        }
+
+        let claimed = GenerationCacheEntry::with_ttl(generation, now, LOOKUP_TIMEOUT_SECONDS);
+        let Ok(serialized) = serde_json::to_vec(&claimed) else {
+            return generation;
+        };
+        if self
+            .set_shared_data(GENERATION_CACHE_KEY, Some(&serialized), cas)
+            .is_err()
+        {
+            // Another request is already checking it
+            return generation;
+        }
+
+        match self.dispatch_gcs_lookup(&build_generation_path(&self.config.gcs_bucket)) {
+            Ok(token) => self.generation_request = Some(token),
+            Err(e) => warn!("Failed to dispatch cache generation check: {:?}", e),
+        }
+        generation
+    }
+
+    /// Store the fetched cache generation. A missing generation object
+    /// counts as generation 0; on other failures the claim lapses and the
+    /// check is retried.
+    fn handle_generation_response(&self, body_size: usize) {
+        let status = self.get_http_call_response_header(":status");
+        let generation = match status.as_deref() {
+            Some("200") => {
+                let body = self
+                    .get_http_call_response_body(0, body_size)
+                    .and_then(|body| String::from_utf8(body).ok())
+                    .unwrap_or_default();
+
+                match parse_generation_record(&body) {
+                    Ok(generation) => generation,
+                    Err(e) => {
+                        warn!("Invalid cache generation: {}", e);
+                        return;
                     }
                 }
             }
+            Some("404") => 0,
+            _ => {
+                warn!("Cache generation check failed, status: {:?}", status);
+                return;
+            }
+        };
+
+        if generation != self.generation {
+            info!(
+                "Cache generation changed from {} to {}",
+                self.generation, generation
+            );
+        }
+
+        let cache_entry = GenerationCacheEntry::with_ttl(
+            generation,
+            self.now(),
+            self.config.generation_check_seconds,
+        );
+        if let Ok(serialized) = serde_json::to_vec(&cache_entry)
+            && let Err(e) = self.set_shared_data(GENERATION_CACHE_KEY, Some(&serialized), None)
+        {
+            warn!("Failed to cache generation {}: {:?}", generation, e);
+        }
+    }
+
+    fn lookup_alias(&mut self, host: &str) -> Result<Option<AliasCacheEntry>, Action> {
+        let key = generate_alias_cache_key(host);
+        if let (Some(cached_data), _) = self.get_shared_data(&key)
+            && let Ok(cache_entry) = serde_json::from_slice::<AliasCacheEntry>(&cached_data)
+            && cache_entry.generation == self.generation
+            && cache_entry.is_valid(self.now())
+        {
+            return Ok(Some(cache_entry));
+        }
+
+        let path = build_alias_path(&self.config.gcs_bucket, host);
+
+        match self.dispatch_gcs_lookup(&path) {
+            Ok(token) => {
+                self.pending_request = Some(token);
+                info!("Looking up alias for host {} in GCS", host);
+                Err(Action::Pause)
+            }
+            Err(e) => {
+                warn!("Failed to dispatch alias request for host {}: {:?}", host, e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Parse and cache an alias lookup response. Hosts without an alias are
+    /// cached as such; backend errors are not cached.
+    fn handle_alias_response(&self, host: &str, body_size: usize) -> Option<String> {
+        let status = self.get_http_call_response_header(":status");
+
+        let tenant = match status.as_deref() {
+            Some("200") => {
+                let body = self
+                    .get_http_call_response_body(0, body_size)
+                    .and_then(|body| String::from_utf8(body).ok())
+                    .unwrap_or_default();
+
+                match parse_alias_record(&body) {
+                    Ok(tenant) => {
+                        info!("Host {} is an alias for tenant {}", host, tenant);
+                        Some(tenant)
+                    }
+                    Err(e) => {
+                        warn!("Invalid alias record for host {}: {}", host, e);
+                        None
+                    }
+                }
+            }
+            Some("404") => None,
+            _ => {
+                warn!("Alias lookup for host {} failed, status: {:?}", host, status);
+                return None;
+            }
+        };
+
+        let mut cache_entry =
+            AliasCacheEntry::with_ttl(tenant.clone(), self.now(), self.config.cache_ttl_seconds);
+        cache_entry.generation = self.generation;
+        let key = generate_alias_cache_key(host);
+        if let Ok(serialized) = serde_json::to_vec(&cache_entry)
+            && let Err(e) = self.set_shared_data(&key, Some(&serialized), None)
+        {
+            warn!("Failed to cache alias for host {}: {:?}", host, e);
+        }
+
+        tenant
+    }
+
+    /// Parse a mapping lookup response for `tenant`, or say how the lookup
+    /// failed. A 404 or an invalid record is cached as a missing mapping, so
+    /// requests for the tenant don't refetch it; backend errors are logged.
+    fn read_mapping_response(
+        &mut self,
+        tenant: &str,
+        body_size: usize,
+    ) -> Result<MappingRecord, LookupOutcome> {
+        let status = self.get_http_call_response_header(":status");
+        let body = match status.as_deref() {
+            Some("200") => self
+                .get_http_call_response_body(0, body_size)
+                .and_then(|body| String::from_utf8(body).ok())
+                .unwrap_or_default(),
+            Some("404") => {
+                info!("No mapping for tenant {}", tenant);
+                self.cache_missing_tenant(tenant);
+                return Err(LookupOutcome::NotFound);
+            }
+            _ => {
+                warn!(
+                    "Mapping lookup for tenant {} failed, status: {:?}",
+                    tenant, status
+                );
+                return Err(LookupOutcome::BackendError);
+            }
+        };
+
+        match self.config.parse_mapping(&body) {
+            Ok(record) => Ok(record),
+            Err(e) => {
+                if let MappingError::UnknownShard(_) = e {
+                    warn!("Rejected mapping record for tenant {}: {}", tenant, e);
+
+                    if let Some(metric) = self.unknown_shard_metric {
+                        let _ = proxy_wasm::hostcalls::increment_metric(metric, 1);
+                    }
+                } else {
+                    warn!("Invalid mapping record for tenant {}: {}", tenant, e);
+                }
+                self.cache_missing_tenant(tenant);
+                Err(LookupOutcome::InvalidRecord)
+            }
+        }
+    }
+
+    fn cache_mapping(&self, tenant: &str, record: MappingRecord) -> CacheEntry {
+        let mut cache_entry = self.config.cache_entry(tenant, record, self.now());
+        cache_entry.generation = self.generation;
+
+        if !tenant.is_empty() {
+            let key = generate_cache_key(tenant);
+            if let Ok(serialized) = serde_json::to_vec(&cache_entry)
+                && let Err(e) = self.set_shared_data(&key, Some(&serialized), None)
+            {
+                warn!("Failed to cache tenant {}: {:?}", tenant, e);
+            }
+
+            info!("Cached tenant {} -> {}", tenant, cache_entry.shard);
+        }
+
+        cache_entry
+    }
+
+    /// Remember that `tenant` has no usable mapping, because it is missing
+    /// or invalid, so requests for it skip the lookup until the negative
+    /// cache entry expires. Any cached mapping is dropped.
+    fn cache_missing_tenant(&self, tenant: &str) {
+        let key = generate_cache_key(tenant);
+        if let Err(e) = self.set_shared_data(&key, None, None) {
+            warn!("Failed to drop cached mapping for tenant {}: {:?}", tenant, e);
+        }
+
+        let ttl = self.config.negative_cache_ttl_seconds;
+        if ttl == 0 {
+            return;
+        }
+
+        let mut cache_entry = NegativeCacheEntry::with_ttl(self.now(), ttl);
+        cache_entry.generation = self.generation;
+        let key = generate_negative_cache_key(tenant);
+        if let Ok(serialized) = serde_json::to_vec(&cache_entry)
+            && let Err(e) = self.set_shared_data(&key, Some(&serialized), None)
+        {
+            warn!("Failed to cache missing tenant {}: {:?}", tenant, e);
+        }
+    }
+
+    /// Refresh `tenant`'s mapping in the background while its cached entry is
+    /// served. The entry's next refresh is first postponed with
+    /// compare-and-swap, so only one request across all workers dispatches
+    /// the refresh. If the refresh is lost (e.g. the request finishes
+    /// first), another request retries once the claim lapses.
+    fn start_refresh(&mut self, tenant: &str, entry: &CacheEntry, cas: Option<u32>) {
+        let mut claimed = entry.clone();
+        claimed.postpone_refresh(self.now() + LOOKUP_TIMEOUT_SECONDS);
+
+        let Ok(serialized) = serde_json::to_vec(&claimed) else {
+            return;
+        };
+        let key = generate_cache_key(tenant);
+        if self.set_shared_data(&key, Some(&serialized), cas).is_err()
+        {
+            // Another request is already refreshing it
+            return;
+        }
+
+        let path = build_gcs_path(&self.config.gcs_bucket, tenant);
+        match self.dispatch_gcs_lookup(&path) {
+            Ok(token) => {
+                info!("Refreshing tenant {} in the background", tenant);
+                self.refresh_request = Some((token, tenant.to_string()));
+            }
+            Err(e) => warn!("Failed to dispatch refresh for tenant {}: {:?}", tenant, e),
+        }
+    }
+
+    /// Cached entry for `tenant`, else dispatch a lookup and pause. Expired
+    /// entries are returned while they are refreshed, or when the lookup
+    /// cannot be dispatched, within the configured stale windows. `Ok(None)`
+    /// means the tenant is known to have no mapping or the lookup could not be
+    /// dispatched, unless the backend error policy rejected the request.
+    fn lookup_tenant_shard(&mut self, tenant: &str) -> Result<Option<CacheEntry>, Action> {
+        self.stale_entry = None;
+
+        // Check cache first
+        let key = generate_cache_key(tenant);
+        if let (Some(cached_data), cas) = self.get_shared_data(&key)
+            && let Ok(cache_entry) = serde_json::from_slice::<CacheEntry>(&cached_data)
+            && cache_entry.generation == self.generation
+        {
+            match cache_entry.freshness(self.now()) {
+                Freshness::Fresh => {
+                    info!(
+                        "Using cached shard for tenant {}: {}",
+                        tenant, cache_entry.shard
+                    );
+                    return Ok(Some(cache_entry));
+                }
+                Freshness::RefreshAhead => {
+                    info!(
+                        "Using cached shard for tenant {} and refreshing ahead of expiry: {}",
+                        tenant, cache_entry.shard
+                    );
+                    self.start_refresh(tenant, &cache_entry, cas);
+                    return Ok(Some(cache_entry));
+                }
+                Freshness::Revalidate => {
+                    info!(
+                        "Using stale shard for tenant {} while refreshing: {}",
+                        tenant, cache_entry.shard
+                    );
+                    // Unless another request already claimed the refresh
+                    if !cache_entry.refresh_postponed(self.now()) {
+                        self.start_refresh(tenant, &cache_entry, cas);
+                    }
+                    return Ok(Some(cache_entry));
+                }
+                // Look it up, falling back to the stale entry on failure
+                Freshness::Stale if self.config.backend_error_policy.serves_stale() => {
+                    self.stale_entry = Some(cache_entry)
+                }
+                Freshness::Stale | Freshness::Expired => {}
+            }
+        }
+
+        let key = generate_negative_cache_key(tenant);
+        if let (Some(cached_data), _) = self.get_shared_data(&key)
+            && let Ok(cache_entry) = serde_json::from_slice::<NegativeCacheEntry>(&cached_data)
+            && cache_entry.generation == self.generation
+            && cache_entry.is_valid(self.now())
+        {
+            info!("Tenant {} has no mapping (cached)", tenant);
+            return Ok(None);
         }
 
         // Not in cache, need to look it up
@@ -281,8 +802,61 @@ impl TenantRouter {
                     "Failed to dispatch GCS request for tenant {}: {:?}",
                     tenant, e
                 );
-                Ok(None)
+
+                if self.stale_entry.is_none()
+                    && self.config.backend_error_policy == BackendErrorPolicy::FailClosed
+                {
+                    self.reject_unavailable(tenant);
+                    return Err(Action::Pause);
+                }
+                Ok(self.stale_entry.take())
             }
         }
     }
+
+    /// Reply 503 because the mapping store failed and the backend error
+    /// policy is `fail_closed`.
+    fn reject_unavailable(&self, tenant: &str) {
+        warn!(
+            "Mapping store unavailable, rejecting request for tenant {}",
+            tenant
+        );
+
+        self.send_http_response(
+            503,
+            vec![],
+            Some(b"Tenant routing is unavailable, retry later"),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tenant_routing_core::{tenant::BaseDomain, test_vectors::HOST_VECTORS};
+
+    #[test]
+    fn test_host_vectors() {
+        for vector in HOST_VECTORS {
+            let plugin_config = serde_json::json!({
+                "base_domains": BaseDomain::from_spec(vector.base_domains).unwrap(),
+            })
+            .to_string();
+            let config = load_plugin_config(Some(plugin_config.as_bytes())).unwrap();
+            let request = TenantRequest {
+                host: vector.authority,
+                path: "/",
+                headers: &|_| None,
+                alias: None,
+            };
+
+            assert_eq!(
+                config.extract_request_tenant(&request).as_deref(),
+                vector.tenant,
+                "authority {:?} with base domains {:?}",
+                vector.authority,
+                vector.base_domains
+            );
+        }
+    }
 }